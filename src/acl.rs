@@ -0,0 +1,294 @@
+use std::fmt;
+use std::io;
+use std::str;
+
+use crate::other;
+
+/// A POSIX access control list, as stored by GNU tar and star in the
+/// `SCHILY.acl.access` and `SCHILY.acl.default` pax records.
+///
+/// These records hold the text form of an ACL: entries like `user:lisa:r--`
+/// separated by commas or newlines, where star also appends the numeric id
+/// of named users and groups as a fourth field.
+///
+/// # Examples
+///
+/// ```
+/// use tar::{Acl, AclTag};
+///
+/// let acl = Acl::parse(b"user::rw-,user:lisa:r--:1000,group::r--,mask::r--,other::---").unwrap();
+/// let lisa = &acl.entries()[1];
+/// assert_eq!(lisa.tag(), AclTag::User);
+/// assert_eq!(lisa.name(), Some("lisa"));
+/// assert_eq!(lisa.id(), Some(1000));
+/// assert_eq!(lisa.perms(), 0o4);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Acl {
+    entries: Vec<AclEntry>,
+}
+
+/// The kind of an [`AclEntry`], saying who its permissions apply to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum AclTag {
+    /// The owner of the file (`user::`).
+    UserObj,
+    /// A user named by the entry (`user:name:`).
+    User,
+    /// The group of the file (`group::`).
+    GroupObj,
+    /// A group named by the entry (`group:name:`).
+    Group,
+    /// The most permissions granted to named users and to groups (`mask::`).
+    Mask,
+    /// Everyone else (`other::`).
+    Other,
+}
+
+/// A single entry of an [`Acl`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AclEntry {
+    tag: AclTag,
+    name: Option<String>,
+    id: Option<u64>,
+    perms: u32,
+}
+
+impl Acl {
+    /// Parses the text form of an ACL, as found in the value of a
+    /// `SCHILY.acl.*` record.
+    ///
+    /// Entries may be separated by commas or newlines, tags may be
+    /// abbreviated to their first letter, and comments starting with `#` are
+    /// ignored, as in the output of `getfacl`.
+    pub fn parse(text: &[u8]) -> io::Result<Acl> {
+        let text = str::from_utf8(text).map_err(|_| other("ACL is not valid UTF-8"))?;
+        let mut entries = Vec::new();
+        for entry in text.split(&[',', '\n'][..]) {
+            let entry = match entry.find('#') {
+                Some(i) => &entry[..i],
+                None => entry,
+            };
+            let entry = entry.trim();
+            if entry.is_empty() {
+                continue;
+            }
+            match AclEntry::parse(entry) {
+                Some(entry) => entries.push(entry),
+                None => return Err(other(&format!("invalid ACL entry `{}`", entry))),
+            }
+        }
+        Ok(Acl { entries })
+    }
+
+    /// Returns the entries of this ACL, in the order they were listed.
+    pub fn entries(&self) -> &[AclEntry] {
+        &self.entries
+    }
+
+    /// Decodes the value of a Linux `system.posix_acl_*` extended attribute,
+    /// mapping the id of each named user and group with `map`.
+    #[cfg(all(target_os = "linux", feature = "acl"))]
+    pub(crate) fn from_xattr<F>(value: &[u8], mut map: F) -> io::Result<Acl>
+    where
+        F: FnMut(AclTag, u64) -> io::Result<u64>,
+    {
+        let invalid = || other("invalid POSIX ACL extended attribute");
+        let (version, value) = match value {
+            [a, b, c, d, rest @ ..] => (u32::from_le_bytes([*a, *b, *c, *d]), rest),
+            _ => return Err(invalid()),
+        };
+        if version != ACL_XATTR_VERSION || value.len() % 8 != 0 {
+            return Err(invalid());
+        }
+        let mut entries = Vec::new();
+        for raw in value.chunks(8) {
+            let tag = u16::from_le_bytes([raw[0], raw[1]]);
+            let perms = u16::from_le_bytes([raw[2], raw[3]]);
+            let id = u32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]);
+            let tag = AclTag::from_xattr(tag).ok_or_else(invalid)?;
+            let id = match tag {
+                AclTag::User | AclTag::Group => Some(map(tag, id.into())?),
+                _ => None,
+            };
+            entries.push(AclEntry {
+                tag,
+                name: None,
+                id,
+                perms: u32::from(perms) & 0o7,
+            });
+        }
+        Ok(Acl { entries })
+    }
+
+    /// Encodes this ACL as the value of a Linux `system.posix_acl_*`
+    /// extended attribute, with `resolve` giving the id of each named user
+    /// and group on this host.
+    #[cfg(all(target_os = "linux", feature = "acl"))]
+    pub(crate) fn to_xattr<F>(&self, mut resolve: F) -> io::Result<Vec<u8>>
+    where
+        F: FnMut(&AclEntry) -> io::Result<u32>,
+    {
+        let mut entries = Vec::with_capacity(self.entries.len());
+        for entry in &self.entries {
+            let id = match entry.tag {
+                AclTag::User | AclTag::Group => resolve(entry)?,
+                _ => ACL_UNDEFINED_ID,
+            };
+            entries.push((entry.tag, id, entry.perms as u16));
+        }
+        // The kernel expects entries ordered by tag, then by id.
+        entries.sort_unstable();
+
+        let mut value = ACL_XATTR_VERSION.to_le_bytes().to_vec();
+        for (tag, id, perms) in entries {
+            value.extend_from_slice(&tag.to_xattr().to_le_bytes());
+            value.extend_from_slice(&perms.to_le_bytes());
+            value.extend_from_slice(&id.to_le_bytes());
+        }
+        Ok(value)
+    }
+}
+
+/// Formats the ACL in its text form, with entries separated by commas.
+impl fmt::Display for Acl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+impl AclEntry {
+    fn parse(entry: &str) -> Option<AclEntry> {
+        let mut fields = entry.split(':');
+        let tag = fields.next()?;
+        let unqualified = matches!(tag, "mask" | "m" | "other" | "o");
+        let fields = fields.collect::<Vec<_>>();
+        let (qualifier, perms, id) = match fields[..] {
+            [perms] if unqualified => ("", perms, None),
+            [qualifier, perms] => (qualifier, perms, None),
+            [qualifier, perms, id] => (qualifier, perms, Some(id.parse().ok()?)),
+            _ => return None,
+        };
+        let tag = match (tag, qualifier.is_empty()) {
+            ("user" | "u", true) => AclTag::UserObj,
+            ("user" | "u", false) => AclTag::User,
+            ("group" | "g", true) => AclTag::GroupObj,
+            ("group" | "g", false) => AclTag::Group,
+            ("mask" | "m", true) => AclTag::Mask,
+            ("other" | "o", true) => AclTag::Other,
+            _ => return None,
+        };
+        let (name, id) = match qualifier.parse::<u64>() {
+            Ok(qualifier) => (None, Some(qualifier)),
+            Err(_) if qualifier.is_empty() => (None, id),
+            Err(_) => (Some(qualifier.to_string()), id),
+        };
+        if id.is_some() && !matches!(tag, AclTag::User | AclTag::Group) {
+            return None;
+        }
+        let mut bits = 0;
+        for c in perms.chars() {
+            bits |= match c {
+                'r' => 0o4,
+                'w' => 0o2,
+                'x' => 0o1,
+                '-' => 0,
+                _ => return None,
+            };
+        }
+        Some(AclEntry {
+            tag,
+            name,
+            id,
+            perms: bits,
+        })
+    }
+
+    /// Returns who this entry applies to.
+    pub fn tag(&self) -> AclTag {
+        self.tag
+    }
+
+    /// Returns the name of the user or group this entry applies to, if it's
+    /// given by name.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the numeric id of the user or group this entry applies to,
+    /// if it's recorded.
+    ///
+    /// This is either the qualifier of the entry when it's numeric, or the
+    /// id which star records after the permissions of named entries.
+    pub fn id(&self) -> Option<u64> {
+        self.id
+    }
+
+    /// Returns the permissions granted by this entry, as the read (`0o4`),
+    /// write (`0o2`) and execute (`0o1`) bits.
+    pub fn perms(&self) -> u32 {
+        self.perms
+    }
+}
+
+impl fmt::Display for AclEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tag = match self.tag {
+            AclTag::UserObj | AclTag::User => "user",
+            AclTag::GroupObj | AclTag::Group => "group",
+            AclTag::Mask => "mask",
+            AclTag::Other => "other",
+        };
+        f.write_str(tag)?;
+        f.write_str(":")?;
+        match (&self.name, self.id) {
+            (Some(name), _) => f.write_str(name)?,
+            (None, Some(id)) => write!(f, "{}", id)?,
+            (None, None) => {}
+        }
+        let bit = |bit, c| if self.perms & bit != 0 { c } else { '-' };
+        write!(f, ":{}{}{}", bit(0o4, 'r'), bit(0o2, 'w'), bit(0o1, 'x'))?;
+        if let (Some(_), Some(id)) = (&self.name, self.id) {
+            write!(f, ":{}", id)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "acl"))]
+const ACL_XATTR_VERSION: u32 = 2;
+#[cfg(all(target_os = "linux", feature = "acl"))]
+const ACL_UNDEFINED_ID: u32 = u32::MAX;
+
+#[cfg(all(target_os = "linux", feature = "acl"))]
+impl AclTag {
+    fn to_xattr(self) -> u16 {
+        match self {
+            AclTag::UserObj => 0x01,
+            AclTag::User => 0x02,
+            AclTag::GroupObj => 0x04,
+            AclTag::Group => 0x08,
+            AclTag::Mask => 0x10,
+            AclTag::Other => 0x20,
+        }
+    }
+
+    fn from_xattr(tag: u16) -> Option<AclTag> {
+        Some(match tag {
+            0x01 => AclTag::UserObj,
+            0x02 => AclTag::User,
+            0x04 => AclTag::GroupObj,
+            0x08 => AclTag::Group,
+            0x10 => AclTag::Mask,
+            0x20 => AclTag::Other,
+            _ => return None,
+        })
+    }
+}
@@ -1,18 +1,23 @@
 use std::cell::{Cell, RefCell};
 use std::cmp;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fs;
 use std::io::prelude::*;
 use std::io::{self, SeekFrom};
 use std::marker;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 
+use crate::decoder::{Chunk, Decoded, DecodedEntry, Decoder};
+use crate::digest::Digests;
 use crate::entry::{EntryFields, EntryIo};
 use crate::error::TarError;
 use crate::header::BLOCK_SIZE;
 use crate::other;
-use crate::pax::*;
-use crate::{Entry, GnuExtSparseHeader, GnuSparseHeader, Header};
+use crate::Entry;
+use crate::{
+    Digest, IdLookup, IdMap, OwnershipPolicy, PaxExtensions, SpecialFilePolicy, SystemIdLookup,
+};
 
 /// A top-level representation of an archive file.
 ///
@@ -23,14 +28,27 @@ pub struct Archive<R: ?Sized + Read> {
 
 pub struct ArchiveInner<R: ?Sized> {
     pos: Cell<u64>,
+    options: ArchiveOptions,
+    digests: Option<RefCell<Digests>>,
+    obj: RefCell<R>,
+}
+
+/// The settings of an archive, which are handed down to its entries.
+pub(crate) struct ArchiveOptions {
     mask: u32,
     unpack_xattrs: bool,
+    unpack_acls: bool,
     preserve_permissions: bool,
     preserve_ownerships: bool,
     preserve_mtime: bool,
     overwrite: bool,
     ignore_zeros: bool,
-    obj: RefCell<R>,
+    strip_components: usize,
+    ownership_policy: OwnershipPolicy,
+    special_files: SpecialFilePolicy,
+    id_lookup: Box<dyn IdLookup + Send>,
+    uid_map: Option<IdMap>,
+    gid_map: Option<IdMap>,
 }
 
 /// An iterator over the entries of an archive.
@@ -42,12 +60,16 @@ pub struct Entries<'a, R: 'a + Read> {
 trait SeekRead: Read + Seek {}
 impl<R: Read + Seek> SeekRead for R {}
 
+/// Chooses where an entry is unpacked, handing the entry back alongside the
+/// path so the caller can keep using it.
+type UnpackFilter<'a> =
+    dyn FnMut(EntryFields<'a>) -> io::Result<(EntryFields<'a>, Option<PathBuf>)> + 'a;
+
 struct EntriesFields<'a> {
     archive: &'a Archive<dyn Read + 'a>,
     seekable_archive: Option<&'a Archive<dyn SeekRead + 'a>>,
-    next: u64,
+    decoder: Decoder,
     done: bool,
-    raw: bool,
 }
 
 impl<R: Read> Archive<R> {
@@ -55,13 +77,8 @@ impl<R: Read> Archive<R> {
     pub fn new(obj: R) -> Archive<R> {
         Archive {
             inner: ArchiveInner {
-                mask: u32::MIN,
-                unpack_xattrs: false,
-                preserve_permissions: false,
-                preserve_ownerships: false,
-                preserve_mtime: true,
-                overwrite: true,
-                ignore_zeros: false,
+                options: ArchiveOptions::default(),
+                digests: None,
                 obj: RefCell::new(obj),
                 pos: Cell::new(0),
             },
@@ -120,7 +137,78 @@ impl<R: Read> Archive<R> {
     /// ```
     pub fn unpack<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<()> {
         let me: &mut Archive<dyn Read> = self;
-        me._unpack(dst.as_ref())
+        me._unpack(dst.as_ref(), &mut |entry| {
+            let path = entry.unpack_path()?;
+            Ok((entry, path))
+        })
+    }
+
+    /// Unpacks the contents tarball into the specified `dst`, deciding where
+    /// each entry goes with `filter`.
+    ///
+    /// The `filter` is called with each entry in turn, and can look at its
+    /// header and path but not read its contents. Returning `None` skips
+    /// the entry, while returning `Some(path)` extracts it to `path` relative
+    /// to `dst`. Otherwise this behaves like `unpack`: the same protections
+    /// against writing outside of `dst` are applied to the returned paths, and
+    /// directories are still created last.
+    ///
+    /// Hard links to an entry which the filter moved are made to its new
+    /// path, like GNU tar's `--transform`. The targets of other hard links are
+    /// used as they're listed in the archive, without removing any components
+    /// set with `set_strip_components`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// // Only extract the `docs` directory, renaming it to `manual`.
+    /// ar.unpack_with("foo", |entry| {
+    ///     let path = entry.path()?;
+    ///     Ok(path
+    ///         .strip_prefix("docs")
+    ///         .ok()
+    ///         .map(|rest| std::path::Path::new("manual").join(rest)))
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn unpack_with<P, F>(&mut self, dst: P, mut filter: F) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&Entry<'_, R>) -> io::Result<Option<PathBuf>>,
+    {
+        // Paths in the archive are compared without any `.` components.
+        fn normalize(path: &Path) -> PathBuf {
+            path.components()
+                .filter(|c| *c != Component::CurDir)
+                .collect()
+        }
+
+        let me: &mut Archive<dyn Read> = self;
+        let mut moved = HashMap::new();
+        me._unpack(dst.as_ref(), &mut |entry| {
+            let entry = entry.into_entry();
+            let path = filter(&entry)?;
+            let mut fields = EntryFields::from(entry);
+            if let Some(path) = &path {
+                if fields.header.entry_type().is_hard_link() {
+                    if let Some(target) = fields.link_name()? {
+                        let target = match moved.get(&normalize(&target)) {
+                            Some(moved) => PathBuf::clone(moved),
+                            None => target.into_owned(),
+                        };
+                        fields.hard_link_target = Some(target);
+                    }
+                }
+                if let Ok(from) = fields.path() {
+                    moved.insert(normalize(&from), path.clone());
+                }
+            }
+            Ok((fields, path))
+        })
     }
 
     /// Set the mask of the permission bits when unpacking this entry.
@@ -134,7 +222,7 @@ impl<R: Read> Archive<R> {
     ///
     /// The mask is 0 by default and is currently only implemented on Unix.
     pub fn set_mask(&mut self, mask: u32) {
-        self.inner.mask = mask;
+        self.inner.options.mask = mask;
     }
 
     /// Indicate whether extended file attributes (xattrs on Unix) are preserved
@@ -145,7 +233,22 @@ impl<R: Read> Archive<R> {
     /// Windows, however, if other archive implementations are found which do
     /// this as well.
     pub fn set_unpack_xattrs(&mut self, unpack_xattrs: bool) {
-        self.inner.unpack_xattrs = unpack_xattrs;
+        self.inner.options.unpack_xattrs = unpack_xattrs;
+    }
+
+    /// Indicate whether POSIX ACLs recorded in `SCHILY.acl.access` and
+    /// `SCHILY.acl.default` records are applied to unpacked files and
+    /// directories.
+    ///
+    /// The names and recorded ids of named users and groups are chosen
+    /// between like those of the owners of entries, following the
+    /// `OwnershipPolicy`, and mapped the same way. Entries which record no id
+    /// are resolved by name with the archive's `IdLookup` whatever the policy.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Linux with the `acl` feature.
+    pub fn set_unpack_acls(&mut self, unpack_acls: bool) {
+        self.inner.options.unpack_acls = unpack_acls;
     }
 
     /// Indicate whether extended permissions (like suid on Unix) are preserved
@@ -154,7 +257,7 @@ impl<R: Read> Archive<R> {
     /// This flag is disabled by default and is currently only implemented on
     /// Unix.
     pub fn set_preserve_permissions(&mut self, preserve: bool) {
-        self.inner.preserve_permissions = preserve;
+        self.inner.options.preserve_permissions = preserve;
     }
 
     /// Indicate whether numeric ownership ids (like uid and gid on Unix)
@@ -163,12 +266,69 @@ impl<R: Read> Archive<R> {
     /// This flag is disabled by default and is currently only implemented on
     /// Unix.
     pub fn set_preserve_ownerships(&mut self, preserve: bool) {
-        self.inner.preserve_ownerships = preserve;
+        self.inner.options.preserve_ownerships = preserve;
+    }
+
+    /// Configure how preserved ownerships are determined from the ids and
+    /// user/group names recorded for each entry.
+    ///
+    /// This is `OwnershipPolicy::Numeric` by default, and only has an effect
+    /// when `set_preserve_ownerships` is enabled.
+    pub fn set_ownership_policy(&mut self, policy: OwnershipPolicy) {
+        self.inner.options.ownership_policy = policy;
+    }
+
+    /// Configure how character devices, block devices and FIFOs are unpacked.
+    ///
+    /// This is `SpecialFilePolicy::CreateOrSkip` by default, which creates
+    /// them where permitted and otherwise skips them.
+    pub fn set_special_file_policy(&mut self, policy: SpecialFilePolicy) {
+        self.inner.options.special_files = policy;
+    }
+
+    /// Set the database used to resolve user and group names to ids when the
+    /// ownership policy consults names.
+    ///
+    /// This is `SystemIdLookup` by default.
+    pub fn set_id_lookup<L: IdLookup + Send + 'static>(&mut self, lookup: L) {
+        self.inner.options.id_lookup = Box::new(lookup);
+    }
+
+    /// Translate the uids recorded in the archive to uids on the host when
+    /// preserving ownerships.
+    ///
+    /// The map is applied to the final uid of each entry, after any names have
+    /// been resolved according to the ownership policy, and unpacking fails
+    /// for entries whose uid isn't mapped. By default uids aren't translated.
+    pub fn set_uid_map(&mut self, map: IdMap) {
+        self.inner.options.uid_map = Some(map);
+    }
+
+    /// Translate the gids recorded in the archive to gids on the host when
+    /// preserving ownerships.
+    ///
+    /// This is the group counterpart of `set_uid_map`.
+    pub fn set_gid_map(&mut self, map: IdMap) {
+        self.inner.options.gid_map = Some(map);
+    }
+
+    /// Remove the first `n` normal components of each entry's path when
+    /// unpacking, like GNU tar's `--strip-components`.
+    ///
+    /// Entries with no more than `n` components are skipped, and the same
+    /// number of components is removed from the targets of hard links. Only
+    /// the paths used by `unpack` and `Entry::unpack_in` are affected; paths
+    /// returned by an `unpack_with` filter, and the targets of hard links
+    /// unpacked with it, are used as is.
+    ///
+    /// By default no components are removed.
+    pub fn set_strip_components(&mut self, n: usize) {
+        self.inner.options.strip_components = n;
     }
 
     /// Indicate whether files and symlinks should be overwritten on extraction.
     pub fn set_overwrite(&mut self, overwrite: bool) {
-        self.inner.overwrite = overwrite;
+        self.inner.options.overwrite = overwrite;
     }
 
     /// Indicate whether access time information is preserved when unpacking
@@ -176,7 +336,7 @@ impl<R: Read> Archive<R> {
     ///
     /// This flag is enabled by default.
     pub fn set_preserve_mtime(&mut self, preserve: bool) {
-        self.inner.preserve_mtime = preserve;
+        self.inner.options.preserve_mtime = preserve;
     }
 
     /// Ignore zeroed headers, which would otherwise indicate to the archive that it has no more
@@ -184,7 +344,33 @@ impl<R: Read> Archive<R> {
     ///
     /// This can be used in case multiple tar archives have been concatenated together.
     pub fn set_ignore_zeros(&mut self, ignore_zeros: bool) {
-        self.inner.ignore_zeros = ignore_zeros;
+        self.inner.options.ignore_zeros = ignore_zeros;
+    }
+
+    /// Computes digests of the contents of each entry, and of the whole
+    /// archive, with the hash function `digest` as the archive is read.
+    ///
+    /// The digest of each entry is of its contents, with the holes of sparse
+    /// files as zeros, and is available from `Entry::digest` once all of its
+    /// contents have been read or skipped. The digest of the archive is
+    /// available from `archive_digest`.
+    ///
+    /// While digests are being computed all of the archive is read: contents
+    /// of entries are never skipped with `Seek`, and the rest of the reader is
+    /// read once the end of the archive is reached. This must be called
+    /// before any entries are read.
+    pub fn set_digest<D: Digest + 'static>(&mut self, digest: D) {
+        self.inner.digests = Some(RefCell::new(Digests::new(Box::new(digest), false)));
+    }
+
+    /// Returns the digest of everything read from the archive so far, if
+    /// digests are being computed.
+    ///
+    /// This is the digest of the whole archive once all of its entries have
+    /// been iterated over.
+    pub fn archive_digest(&self) -> Option<Vec<u8>> {
+        let digests = self.inner.digests.as_ref()?;
+        Some(digests.borrow().archive_digest())
     }
 }
 
@@ -217,16 +403,22 @@ impl Archive<dyn Read + '_> {
                  position 0",
             ));
         }
+        let mut decoder = Decoder::new();
+        decoder.set_ignore_zeros(self.inner.options.ignore_zeros);
+        if let Some(digests) = &self.inner.digests {
+            digests
+                .borrow_mut()
+                .set_ignore_zeros(self.inner.options.ignore_zeros);
+        }
         Ok(EntriesFields {
             archive: self,
             seekable_archive,
+            decoder,
             done: false,
-            next: 0,
-            raw: false,
         })
     }
 
-    fn _unpack(&mut self, dst: &Path) -> io::Result<()> {
+    fn _unpack<'a>(&'a mut self, dst: &Path, filter: &mut UnpackFilter<'a>) -> io::Result<()> {
         if dst.symlink_metadata().is_err() {
             fs::create_dir_all(dst)
                 .map_err(|e| TarError::new(format!("failed to create `{}`", dst.display()), e))?;
@@ -244,11 +436,16 @@ impl Archive<dyn Read + '_> {
         // extraction.
         let mut directories = Vec::new();
         for entry in self._entries(None)? {
-            let mut file = entry.map_err(|e| TarError::new("failed to iterate over archive", e))?;
-            if file.header().entry_type() == crate::EntryType::Directory {
-                directories.push(file);
+            let file = entry.map_err(|e| TarError::new("failed to iterate over archive", e))?;
+            let (mut file, path) = filter(EntryFields::from(file))?;
+            let path = match path {
+                Some(path) => path,
+                None => continue,
+            };
+            if file.header.entry_type() == crate::EntryType::Directory {
+                directories.push((file, path));
             } else {
-                file.unpack_in(dst)?;
+                file.unpack_in_path(dst, &path)?;
             }
         }
 
@@ -259,9 +456,9 @@ impl Archive<dyn Read + '_> {
         // child directories within those of more restrictive permissions. See [0] for details.
         //
         // [0]: <https://github.com/alexcrichton/tar-rs/issues/242>
-        directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
-        for mut dir in directories {
-            dir.unpack_in(dst)?;
+        directories.sort_by(|a, b| b.1.cmp(&a.1));
+        for (mut dir, path) in directories {
+            dir.unpack_in_path(dst, &path)?;
         }
 
         Ok(())
@@ -274,11 +471,18 @@ impl<'a, R: Read> Entries<'a, R> {
     /// If the raw list of entries is returned, then no preprocessing happens
     /// on account of this library, for example taking into account GNU long name
     /// or long link archive members. Raw iteration is disabled by default.
-    pub fn raw(self, raw: bool) -> Entries<'a, R> {
-        Entries {
-            fields: EntriesFields { raw, ..self.fields },
-            _ignored: marker::PhantomData,
-        }
+    pub fn raw(mut self, raw: bool) -> Entries<'a, R> {
+        self.fields.decoder.set_raw(raw);
+        self
+    }
+
+    /// Returns the records of the pax global extended headers read so far,
+    /// if there are any.
+    ///
+    /// Unlike `Entry::pax_global_extensions` this also covers global headers
+    /// which no entry follows, once iteration has finished.
+    pub fn pax_global_extensions(&self) -> Option<PaxExtensions<'_>> {
+        self.fields.decoder.pax_global_extensions()
     }
 }
 impl<'a, R: Read> Iterator for Entries<'a, R> {
@@ -292,270 +496,68 @@ impl<'a, R: Read> Iterator for Entries<'a, R> {
 }
 
 impl<'a> EntriesFields<'a> {
-    fn next_entry_raw(
-        &mut self,
-        pax_extensions: Option<&[u8]>,
-    ) -> io::Result<Option<Entry<'a, io::Empty>>> {
-        let mut header = Header::new_old();
-        let mut header_pos = self.next;
+    fn next_entry(&mut self) -> io::Result<Option<Entry<'a, io::Empty>>> {
+        let mut block = [0; BLOCK_SIZE as usize];
         loop {
-            // Seek to the start of the next header in the archive
-            let delta = self.next - self.archive.inner.pos.get();
+            // Seek to the next block the decoder needs
+            let delta = self.decoder.position() - self.archive.inner.pos.get();
             self.skip(delta)?;
 
-            // EOF is an indicator that we are at the end of the archive.
-            if !try_read_all(&mut &self.archive.inner, header.as_mut_bytes())? {
-                return Ok(None);
-            }
-
-            // If a header is not all zeros, we have another valid header.
-            // Otherwise, check if we are ignoring zeros and continue, or break as if this is the
-            // end of the archive.
-            if !header.as_bytes().iter().all(|i| *i == 0) {
-                self.next += BLOCK_SIZE;
-                break;
-            }
-
-            if !self.archive.inner.ignore_zeros {
-                return Ok(None);
-            }
-            self.next += BLOCK_SIZE;
-            header_pos = self.next;
-        }
-
-        // Make sure the checksum is ok
-        let sum = header.as_bytes()[..148]
-            .iter()
-            .chain(&header.as_bytes()[156..])
-            .fold(0, |a, b| a + (*b as u32))
-            + 8 * 32;
-        let cksum = header.cksum()?;
-        if sum != cksum {
-            return Err(other("archive header checksum mismatch"));
-        }
-
-        let mut pax_size: Option<u64> = None;
-        if let Some(pax_extensions_ref) = &pax_extensions {
-            pax_size = pax_extensions_value(pax_extensions_ref, PAX_SIZE);
-
-            if let Some(pax_uid) = pax_extensions_value(pax_extensions_ref, PAX_UID) {
-                header.set_uid(pax_uid);
-            }
-
-            if let Some(pax_gid) = pax_extensions_value(pax_extensions_ref, PAX_GID) {
-                header.set_gid(pax_gid);
-            }
-        }
-
-        let file_pos = self.next;
-        let mut size = header.entry_size()?;
-        // If this exists, it must override the header size. Disagreement among
-        // parsers allows construction of malicious archives that appear different
-        // when parsed.
-        if let Some(pax_size) = pax_size {
-            size = pax_size;
-        }
-        let ret = EntryFields {
-            size,
-            header_pos,
-            file_pos,
-            data: vec![EntryIo::Data((&self.archive.inner).take(size))],
-            header,
-            long_pathname: None,
-            long_linkname: None,
-            pax_extensions: None,
-            mask: self.archive.inner.mask,
-            unpack_xattrs: self.archive.inner.unpack_xattrs,
-            preserve_permissions: self.archive.inner.preserve_permissions,
-            preserve_mtime: self.archive.inner.preserve_mtime,
-            overwrite: self.archive.inner.overwrite,
-            preserve_ownerships: self.archive.inner.preserve_ownerships,
-        };
-
-        // Store where the next entry is, rounding up by 512 bytes (the size of
-        // a header);
-        let size = size
-            .checked_add(BLOCK_SIZE - 1)
-            .ok_or_else(|| other("size overflow"))?;
-        self.next = self
-            .next
-            .checked_add(size & !(BLOCK_SIZE - 1))
-            .ok_or_else(|| other("size overflow"))?;
-
-        Ok(Some(ret.into_entry()))
-    }
-
-    fn next_entry(&mut self) -> io::Result<Option<Entry<'a, io::Empty>>> {
-        if self.raw {
-            return self.next_entry_raw(None);
-        }
-
-        let mut gnu_longname = None;
-        let mut gnu_longlink = None;
-        let mut pax_extensions = None;
-        let mut processed = 0;
-        loop {
-            processed += 1;
-            let entry = match self.next_entry_raw(pax_extensions.as_deref())? {
-                Some(entry) => entry,
-                None if processed > 1 => {
-                    return Err(other(
-                        "members found describing a future member \
-                         but no future member found",
-                    ));
-                }
-                None => return Ok(None),
+            let read = try_read_all(&mut &self.archive.inner, &mut block)?;
+            let decoded = if read {
+                self.decoder.decode(&block)?.1
+            } else {
+                Some(self.decoder.finish()?)
             };
-
-            let is_recognized_header =
-                entry.header().as_gnu().is_some() || entry.header().as_ustar().is_some();
-
-            if is_recognized_header && entry.header().entry_type().is_gnu_longname() {
-                if gnu_longname.is_some() {
-                    return Err(other(
-                        "two long name entries describing \
-                         the same member",
-                    ));
-                }
-                gnu_longname = Some(EntryFields::from(entry).read_all()?);
-                continue;
-            }
-
-            if is_recognized_header && entry.header().entry_type().is_gnu_longlink() {
-                if gnu_longlink.is_some() {
-                    return Err(other(
-                        "two long name entries describing \
-                         the same member",
-                    ));
+            match decoded {
+                Some(Decoded::Entry(entry)) => {
+                    // The entry reads its own contents from the archive,
+                    // except for global headers whose records the decoder
+                    // needs for the entries after them.
+                    let global = self.decoder.reads_global_extensions();
+                    self.decoder.skip_data();
+                    let inner = &self.archive.inner;
+                    let data = if global {
+                        let mut data = Vec::new();
+                        inner.take(entry.size).read_to_end(&mut data)?;
+                        if (data.len() as u64) < entry.size {
+                            return Err(other("unexpected EOF in archive entry"));
+                        }
+                        self.decoder.set_global_extensions(&data);
+                        vec![EntryIo::Buffered(io::Cursor::new(data).take(entry.size))]
+                    } else {
+                        entry
+                            .chunks()
+                            .into_iter()
+                            .map(|chunk| match chunk {
+                                Chunk::Data(len) => EntryIo::Data(inner.take(len)),
+                                Chunk::Hole(len) => EntryIo::Pad(io::repeat(0).take(len)),
+                            })
+                            .collect()
+                    };
+                    let mut fields = inner.options.entry_fields(*entry, data);
+                    fields.digests = inner.digests.as_ref();
+                    return Ok(Some(fields.into_entry()));
                 }
-                gnu_longlink = Some(EntryFields::from(entry).read_all()?);
-                continue;
-            }
-
-            if is_recognized_header && entry.header().entry_type().is_pax_local_extensions() {
-                if pax_extensions.is_some() {
-                    return Err(other(
-                        "two pax extensions entries describing \
-                         the same member",
-                    ));
-                }
-                pax_extensions = Some(EntryFields::from(entry).read_all()?);
-                continue;
-            }
-
-            let mut fields = EntryFields::from(entry);
-            fields.long_pathname = gnu_longname;
-            fields.long_linkname = gnu_longlink;
-            fields.pax_extensions = pax_extensions;
-            self.parse_sparse_header(&mut fields)?;
-            return Ok(Some(fields.into_entry()));
-        }
-    }
-
-    fn parse_sparse_header(&mut self, entry: &mut EntryFields<'a>) -> io::Result<()> {
-        if !entry.header.entry_type().is_gnu_sparse() {
-            return Ok(());
-        }
-        let gnu = match entry.header.as_gnu() {
-            Some(gnu) => gnu,
-            None => return Err(other("sparse entry type listed but not GNU header")),
-        };
-
-        // Sparse files are represented internally as a list of blocks that are
-        // read. Blocks are either a bunch of 0's or they're data from the
-        // underlying archive.
-        //
-        // Blocks of a sparse file are described by the `GnuSparseHeader`
-        // structure, some of which are contained in `GnuHeader` but some of
-        // which may also be contained after the first header in further
-        // headers.
-        //
-        // We read off all the blocks here and use the `add_block` function to
-        // incrementally add them to the list of I/O block (in `entry.data`).
-        // The `add_block` function also validates that each chunk comes after
-        // the previous, we don't overrun the end of the file, and each block is
-        // aligned to a 512-byte boundary in the archive itself.
-        //
-        // At the end we verify that the sparse file size (`Header::size`) is
-        // the same as the current offset (described by the list of blocks) as
-        // well as the amount of data read equals the size of the entry
-        // (`Header::entry_size`).
-        entry.data.truncate(0);
-
-        let mut cur = 0;
-        let mut remaining = entry.size;
-        {
-            let data = &mut entry.data;
-            let reader = &self.archive.inner;
-            let size = entry.size;
-            let mut add_block = |block: &GnuSparseHeader| -> io::Result<_> {
-                if block.is_empty() {
-                    return Ok(());
-                }
-                let off = block.offset()?;
-                let len = block.length()?;
-                if len != 0 && (size - remaining) % BLOCK_SIZE != 0 {
-                    return Err(other(
-                        "previous block in sparse file was not \
-                         aligned to 512-byte boundary",
-                    ));
-                } else if off < cur {
-                    return Err(other(
-                        "out of order or overlapping sparse \
-                         blocks",
-                    ));
-                } else if cur < off {
-                    let block = io::repeat(0).take(off - cur);
-                    data.push(EntryIo::Pad(block));
-                }
-                cur = off
-                    .checked_add(len)
-                    .ok_or_else(|| other("more bytes listed in sparse file than u64 can hold"))?;
-                remaining = remaining.checked_sub(len).ok_or_else(|| {
-                    other(
-                        "sparse file consumed more data than the header \
-                         listed",
-                    )
-                })?;
-                data.push(EntryIo::Data(reader.take(len)));
-                Ok(())
-            };
-            for block in gnu.sparse.iter() {
-                add_block(block)?
-            }
-            if gnu.is_extended() {
-                let mut ext = GnuExtSparseHeader::new();
-                ext.isextended[0] = 1;
-                while ext.is_extended() {
-                    if !try_read_all(&mut &self.archive.inner, ext.as_mut_bytes())? {
-                        return Err(other("failed to read extension"));
-                    }
-
-                    self.next += BLOCK_SIZE;
-                    for block in ext.sparse.iter() {
-                        add_block(block)?;
+                Some(Decoded::End) => {
+                    // The digest of the archive covers everything after its
+                    // end too, such as padding to a whole record.
+                    if self.archive.inner.digests.is_some() {
+                        io::copy(&mut &self.archive.inner, &mut io::sink())?;
                     }
+                    return Ok(None);
                 }
+                _ => {}
             }
         }
-        if cur != gnu.real_size()? {
-            return Err(other(
-                "mismatch in sparse file chunks and \
-                 size in header",
-            ));
-        }
-        entry.size = cur;
-        if remaining > 0 {
-            return Err(other(
-                "mismatch in sparse file chunks and \
-                 entry size in header",
-            ));
-        }
-        Ok(())
     }
 
     fn skip(&mut self, mut amt: u64) -> io::Result<()> {
-        if let Some(seekable_archive) = self.seekable_archive {
+        let seekable_archive = match self.archive.inner.digests {
+            Some(_) => None,
+            None => self.seekable_archive,
+        };
+        if let Some(seekable_archive) = seekable_archive {
             let pos = io::SeekFrom::Current(
                 i64::try_from(amt).map_err(|_| other("seek position out of bounds"))?,
             );
@@ -597,10 +599,84 @@ impl<'a> Iterator for EntriesFields<'a> {
     }
 }
 
+impl Default for ArchiveOptions {
+    fn default() -> ArchiveOptions {
+        ArchiveOptions {
+            mask: u32::MIN,
+            unpack_xattrs: false,
+            unpack_acls: false,
+            preserve_permissions: false,
+            preserve_ownerships: false,
+            preserve_mtime: true,
+            overwrite: true,
+            ignore_zeros: false,
+            strip_components: 0,
+            ownership_policy: OwnershipPolicy::Numeric,
+            special_files: SpecialFilePolicy::default(),
+            id_lookup: Box::new(SystemIdLookup),
+            uid_map: None,
+            gid_map: None,
+        }
+    }
+}
+
+impl ArchiveOptions {
+    #[cfg(feature = "tokio")]
+    pub(crate) fn set_ignore_zeros(&mut self, ignore_zeros: bool) {
+        self.ignore_zeros = ignore_zeros;
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn ignore_zeros(&self) -> bool {
+        self.ignore_zeros
+    }
+
+    /// Creates the fields of an entry reading its contents from `data`, with
+    /// the settings of this archive.
+    pub(crate) fn entry_fields<'a>(
+        &'a self,
+        entry: DecodedEntry,
+        data: Vec<EntryIo<'a>>,
+    ) -> EntryFields<'a> {
+        EntryFields {
+            size: entry.size,
+            header_pos: entry.header_pos,
+            file_pos: entry.file_pos,
+            sparse: entry.sparse,
+            data,
+            header: entry.header,
+            raw_header: entry.raw_header,
+            long_pathname: entry.long_pathname,
+            long_linkname: entry.long_linkname,
+            pax_extensions: entry.pax_extensions,
+            effective_extensions: entry.effective_extensions,
+            global_extensions: entry.global_extensions,
+            mask: self.mask,
+            unpack_xattrs: self.unpack_xattrs,
+            unpack_acls: self.unpack_acls,
+            preserve_permissions: self.preserve_permissions,
+            preserve_mtime: self.preserve_mtime,
+            overwrite: self.overwrite,
+            preserve_ownerships: self.preserve_ownerships,
+            strip_components: self.strip_components,
+            hard_link_target: None,
+            ownership_policy: self.ownership_policy,
+            special_files: self.special_files,
+            id_lookup: &*self.id_lookup,
+            uid_map: self.uid_map.as_ref(),
+            gid_map: self.gid_map.as_ref(),
+            digests: None,
+        }
+    }
+}
+
 impl<R: ?Sized + Read> Read for &ArchiveInner<R> {
     fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
         let i = self.obj.borrow_mut().read(into)?;
         self.pos.set(self.pos.get() + i as u64);
+        if let Some(digests) = &self.digests {
+            digests.borrow_mut().update(&into[..i])?;
+        }
         Ok(i)
     }
 }
@@ -617,7 +693,7 @@ impl<R: ?Sized + Seek> Seek for &ArchiveInner<R> {
 ///
 /// If the reader reaches its end before filling the buffer at all, returns `false`.
 /// Otherwise returns `true`.
-fn try_read_all<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+pub(crate) fn try_read_all<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<bool> {
     let mut read = 0;
     while read < buf.len() {
         match r.read(&mut buf[read..])? {
@@ -0,0 +1,394 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf, Take};
+
+use crate::archive::ArchiveOptions;
+use crate::decoder::{Chunk, Decoded, DecodedEntry, Decoder};
+use crate::entry::{EntryFields, EntryIo};
+use crate::header::BLOCK_SIZE;
+use crate::other;
+use crate::pax::{PAX_ATIME, PAX_CTIME};
+use crate::{Header, PaxExtensions};
+
+/// A top-level representation of an archive read with tokio's [`AsyncRead`].
+///
+/// This is the asynchronous counterpart of [`Archive`](crate::Archive),
+/// interpreting long names, pax extensions and sparse files in exactly the
+/// same way. Entries can be read but not unpacked to the filesystem.
+pub struct AsyncArchive<R> {
+    obj: R,
+    pos: u64,
+    options: ArchiveOptions,
+}
+
+/// The entries of an archive read asynchronously.
+///
+/// Entries are returned by [`AsyncEntries::next`], and each must be dropped
+/// before the next one is requested.
+pub struct AsyncEntries<'a, R> {
+    obj: &'a mut R,
+    pos: &'a mut u64,
+    options: &'a ArchiveOptions,
+    decoder: Decoder,
+    done: bool,
+}
+
+/// An entry of an archive read asynchronously.
+///
+/// The contents of the entry are read through its [`AsyncRead`]
+/// implementation.
+pub struct AsyncEntry<'a, R> {
+    fields: EntryFields<'a>,
+    obj: Take<&'a mut R>,
+    pos: &'a mut u64,
+    chunks: VecDeque<Chunk>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncArchive<R> {
+    /// Create a new archive with the underlying object as the reader.
+    pub fn new(obj: R) -> AsyncArchive<R> {
+        AsyncArchive {
+            obj,
+            pos: 0,
+            options: ArchiveOptions::default(),
+        }
+    }
+
+    /// Unwrap this archive, returning the underlying object.
+    pub fn into_inner(self) -> R {
+        self.obj
+    }
+
+    /// Ignore zeroed headers, which would otherwise indicate to the archive that it has no more
+    /// entries.
+    ///
+    /// This can be used in case multiple tar archives have been concatenated together.
+    pub fn set_ignore_zeros(&mut self, ignore_zeros: bool) {
+        self.options.set_ignore_zeros(ignore_zeros);
+    }
+
+    /// Construct a stream of the entries in this archive.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tar::AsyncArchive;
+    /// use tokio::io::AsyncReadExt;
+    ///
+    /// let file = tokio::fs::File::open("foo.tar").await?;
+    /// let mut ar = AsyncArchive::new(file);
+    /// let mut entries = ar.entries()?;
+    /// while let Some(entry) = entries.next().await {
+    ///     let mut entry = entry?;
+    ///     let mut contents = Vec::new();
+    ///     entry.read_to_end(&mut contents).await?;
+    ///     println!("{}: {} bytes", entry.path()?.display(), contents.len());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn entries(&mut self) -> io::Result<AsyncEntries<'_, R>> {
+        if self.pos != 0 {
+            return Err(other(
+                "cannot call entries unless archive is at \
+                 position 0",
+            ));
+        }
+        let mut decoder = Decoder::new();
+        decoder.set_ignore_zeros(self.options.ignore_zeros());
+        Ok(AsyncEntries {
+            obj: &mut self.obj,
+            pos: &mut self.pos,
+            options: &self.options,
+            decoder,
+            done: false,
+        })
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin> AsyncEntries<'a, R> {
+    /// Indicates whether this stream will return raw entries or not.
+    ///
+    /// If the raw list of entries is returned, then no preprocessing happens
+    /// on account of this library, for example taking into account GNU long name
+    /// or long link archive members. Raw iteration is disabled by default.
+    pub fn raw(mut self, raw: bool) -> AsyncEntries<'a, R> {
+        self.decoder.set_raw(raw);
+        self
+    }
+
+    /// Returns the records of the pax global extended headers read so far,
+    /// if there are any.
+    ///
+    /// This behaves like
+    /// [`Entries::pax_global_extensions`](crate::Entries::pax_global_extensions).
+    pub fn pax_global_extensions(&self) -> Option<PaxExtensions<'_>> {
+        self.decoder.pax_global_extensions()
+    }
+
+    /// Returns the next entry in the archive, or `None` once the end of the
+    /// archive has been reached.
+    ///
+    /// Any data of the previous entry which wasn't read is skipped.
+    pub async fn next(&mut self) -> Option<io::Result<AsyncEntry<'_, R>>> {
+        if self.done {
+            return None;
+        }
+        let (entry, contents) = match self.next_entry().await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        };
+        let (data, chunks) = match contents {
+            Some(contents) => {
+                let len = contents.len() as u64;
+                let data = vec![EntryIo::Buffered(io::Read::take(
+                    io::Cursor::new(contents),
+                    len,
+                ))];
+                (data, VecDeque::new())
+            }
+            None => (Vec::new(), entry.chunks().into_iter().collect()),
+        };
+        Some(Ok(AsyncEntry {
+            fields: self.options.entry_fields(entry, data),
+            obj: (&mut *self.obj).take(0),
+            pos: &mut *self.pos,
+            chunks,
+        }))
+    }
+
+    /// Returns the next entry, along with its contents if they had to be
+    /// read already.
+    async fn next_entry(&mut self) -> io::Result<Option<(DecodedEntry, Option<Vec<u8>>)>> {
+        let mut block = [0; BLOCK_SIZE as usize];
+        loop {
+            // Skip to the next block the decoder needs
+            let delta = self.decoder.position() - *self.pos;
+            let skipped =
+                tokio::io::copy(&mut (&mut *self.obj).take(delta), &mut tokio::io::sink()).await?;
+            *self.pos += skipped;
+            if skipped < delta {
+                return Err(other("unexpected EOF during skip"));
+            }
+
+            let read = try_read_all(&mut *self.obj, &mut block).await?;
+            if read {
+                *self.pos += BLOCK_SIZE;
+            }
+            let decoded = if read {
+                self.decoder.decode(&block)?.1
+            } else {
+                Some(self.decoder.finish()?)
+            };
+            match decoded {
+                Some(Decoded::Entry(entry)) => {
+                    let global = self.decoder.reads_global_extensions();
+                    self.decoder.skip_data();
+                    if !global {
+                        return Ok(Some((*entry, None)));
+                    }
+                    // The decoder needs the records of global headers for
+                    // the entries after them.
+                    let mut data = Vec::new();
+                    (&mut *self.obj)
+                        .take(entry.size)
+                        .read_to_end(&mut data)
+                        .await?;
+                    *self.pos += data.len() as u64;
+                    if (data.len() as u64) < entry.size {
+                        return Err(other("unexpected EOF in archive entry"));
+                    }
+                    self.decoder.set_global_extensions(&data);
+                    return Ok(Some((*entry, Some(data))));
+                }
+                Some(Decoded::End) => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin> AsyncEntry<'a, R> {
+    /// Returns the path name for this entry.
+    ///
+    /// This behaves like [`Entry::path`](crate::Entry::path).
+    pub fn path(&self) -> io::Result<Cow<'_, Path>> {
+        self.fields.path()
+    }
+
+    /// Returns the raw bytes listed for this entry.
+    ///
+    /// This behaves like [`Entry::path_bytes`](crate::Entry::path_bytes).
+    pub fn path_bytes(&self) -> Cow<'_, [u8]> {
+        self.fields.path_bytes()
+    }
+
+    /// Returns the link name for this entry, if any is found.
+    ///
+    /// This behaves like [`Entry::link_name`](crate::Entry::link_name).
+    pub fn link_name(&self) -> io::Result<Option<Cow<'_, Path>>> {
+        self.fields.link_name()
+    }
+
+    /// Returns the link name for this entry, in bytes, if listed.
+    ///
+    /// This behaves like [`Entry::link_name_bytes`](crate::Entry::link_name_bytes).
+    pub fn link_name_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        self.fields.link_name_bytes()
+    }
+
+    /// Returns the records of the pax global extended headers earlier in the
+    /// archive.
+    ///
+    /// This behaves like
+    /// [`Entry::pax_global_extensions`](crate::Entry::pax_global_extensions).
+    pub fn pax_global_extensions(&self) -> Option<PaxExtensions<'_>> {
+        self.fields
+            .global_extensions
+            .as_deref()
+            .map(PaxExtensions::new)
+    }
+
+    /// Returns an iterator over the pax extensions contained in this entry.
+    ///
+    /// This behaves like [`Entry::pax_extensions`](crate::Entry::pax_extensions),
+    /// reading the entire entry if the entry itself is a list of extensions.
+    pub async fn pax_extensions(&mut self) -> io::Result<Option<PaxExtensions<'_>>> {
+        let entry_type = self.fields.header.entry_type();
+        if self.fields.pax_extensions.is_none()
+            && (entry_type.is_pax_global_extensions() || entry_type.is_pax_local_extensions())
+        {
+            let mut data = Vec::new();
+            self.read_to_end(&mut data).await?;
+            self.fields.pax_extensions = Some(data);
+        }
+        self.fields.pax_extensions()
+    }
+
+    /// Returns access to the header of this entry in the archive.
+    pub fn header(&self) -> &Header {
+        &self.fields.header
+    }
+
+    /// Returns the modification time of this entry.
+    ///
+    /// This behaves like [`Entry::mtime`](crate::Entry::mtime).
+    pub fn mtime(&self) -> io::Result<SystemTime> {
+        self.fields.mtime()
+    }
+
+    /// Returns the access time of this entry, if recorded in a pax `atime`
+    /// record.
+    pub fn atime(&self) -> io::Result<Option<SystemTime>> {
+        self.fields.pax_time(PAX_ATIME)
+    }
+
+    /// Returns the status change time of this entry, if recorded in a pax
+    /// `ctime` record.
+    pub fn ctime(&self) -> io::Result<Option<SystemTime>> {
+        self.fields.pax_time(PAX_CTIME)
+    }
+
+    /// Returns the size of the contents of this entry.
+    pub fn size(&self) -> u64 {
+        self.fields.size
+    }
+
+    /// Returns the starting position, in bytes, of the header of this entry in
+    /// the archive.
+    pub fn raw_header_position(&self) -> u64 {
+        self.fields.header_pos
+    }
+
+    /// Returns the starting position, in bytes, of the file of this entry in
+    /// the archive.
+    pub fn raw_file_position(&self) -> u64 {
+        self.fields.file_pos
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncEntry<'_, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            let remaining = buf.remaining() as u64;
+            match this.chunks.front_mut() {
+                None if this.fields.data.is_empty() => return Poll::Ready(Ok(())),
+                None => {
+                    let n = io::Read::read(&mut this.fields, buf.initialize_unfilled())?;
+                    buf.advance(n);
+                    return Poll::Ready(Ok(()));
+                }
+                Some(Chunk::Hole(len)) if *len > 0 => {
+                    let n = (*len).min(remaining) as usize;
+                    buf.initialize_unfilled_to(n).fill(0);
+                    buf.advance(n);
+                    *len -= n as u64;
+                    return Poll::Ready(Ok(()));
+                }
+                Some(Chunk::Data(len)) if *len > 0 => {
+                    if this.obj.limit() == 0 {
+                        this.obj.set_limit(*len);
+                    }
+                    let before = buf.filled().len();
+                    match Pin::new(&mut this.obj).poll_read(cx, buf) {
+                        Poll::Ready(Ok(())) => {}
+                        poll => return poll,
+                    }
+                    let n = (buf.filled().len() - before) as u64;
+                    if n == 0 && remaining > 0 {
+                        return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "unexpected EOF in archive entry",
+                        )));
+                    }
+                    *len -= n;
+                    *this.pos += n;
+                    return Poll::Ready(Ok(()));
+                }
+                Some(_) => {
+                    this.chunks.pop_front();
+                }
+            }
+        }
+    }
+}
+
+/// Try to fill the buffer from the reader.
+///
+/// If the reader reaches its end before filling the buffer at all, returns `false`.
+/// Otherwise returns `true`.
+async fn try_read_all<R: AsyncRead + Unpin>(r: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match r.read(&mut buf[read..]).await? {
+            0 => {
+                if read == 0 {
+                    return Ok(false);
+                }
+
+                return Err(other("failed to read entire block"));
+            }
+            n => read += n,
+        }
+    }
+    Ok(true)
+}
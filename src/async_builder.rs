@@ -0,0 +1,158 @@
+use std::io;
+use std::path::Path;
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::builder::{prepare_headers, BuilderOptions};
+use crate::header::BLOCK_SIZE;
+use crate::{ArchiveFormat, Header};
+
+/// A structure for building archives with tokio's [`AsyncWrite`].
+///
+/// This is the asynchronous counterpart of [`Builder`](crate::Builder) for
+/// entries whose contents come from an [`AsyncRead`], writing long paths and
+/// link names in exactly the same way. Entries can't be added from the
+/// filesystem.
+///
+/// Unlike `Builder` the archive isn't finished when this is dropped, so
+/// `finish` or `into_inner` must be awaited once all entries are added.
+pub struct AsyncBuilder<W> {
+    options: BuilderOptions,
+    finished: bool,
+    obj: W,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncBuilder<W> {
+    /// Create a new archive builder with the underlying object as the
+    /// destination of all data written.
+    pub fn new(obj: W) -> AsyncBuilder<W> {
+        AsyncBuilder {
+            options: BuilderOptions::default(),
+            finished: false,
+            obj,
+        }
+    }
+
+    /// Set the format used for paths and link names which don't fit in a
+    /// header. Defaults to `ArchiveFormat::Gnu`.
+    pub fn format(&mut self, format: ArchiveFormat) {
+        self.options.format = format;
+    }
+
+    /// Gets shared reference to the underlying object.
+    pub fn get_ref(&self) -> &W {
+        &self.obj
+    }
+
+    /// Gets mutable reference to the underlying object.
+    ///
+    /// Note that care must be taken while writing to the underlying
+    /// object.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.obj
+    }
+
+    /// Unwrap this archive, returning the underlying object.
+    ///
+    /// This function will finish writing the archive if the `finish` function
+    /// hasn't yet been called, returning any I/O error which happens during
+    /// that operation.
+    pub async fn into_inner(mut self) -> io::Result<W> {
+        self.finish().await?;
+        Ok(self.obj)
+    }
+
+    /// Adds a new entry to this archive.
+    ///
+    /// This behaves like [`Builder::append`](crate::Builder::append): the
+    /// `size` field of `header` must be the same as the length of `data`, and
+    /// its checksum should have been set.
+    pub async fn append<R: AsyncRead + Unpin>(
+        &mut self,
+        header: &Header,
+        mut data: R,
+    ) -> io::Result<()> {
+        self.obj.write_all(header.as_bytes()).await?;
+        let len = tokio::io::copy(&mut data, &mut self.obj).await?;
+        let buf = [0; BLOCK_SIZE as usize];
+        let remaining = BLOCK_SIZE - (len % BLOCK_SIZE);
+        if remaining < BLOCK_SIZE {
+            self.obj.write_all(&buf[..remaining as usize]).await?;
+        }
+        Ok(())
+    }
+
+    /// Adds a new entry to this archive with the specified path.
+    ///
+    /// This behaves like [`Builder::append_data`](crate::Builder::append_data),
+    /// writing a long name extension entry first if the path doesn't fit in
+    /// the header.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # async fn run() -> std::io::Result<()> {
+    /// use tar::{AsyncBuilder, Header};
+    ///
+    /// let mut header = Header::new_gnu();
+    /// header.set_size(4);
+    ///
+    /// let mut ar = AsyncBuilder::new(Vec::new());
+    /// ar.append_data(&mut header, "really/long/path/to/foo", &[1, 2, 3, 4][..])
+    ///     .await?;
+    /// let data = ar.into_inner().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn append_data<P: AsRef<Path>, R: AsyncRead + Unpin>(
+        &mut self,
+        header: &mut Header,
+        path: P,
+        data: R,
+    ) -> io::Result<()> {
+        self.write_headers(header, path.as_ref(), None).await?;
+        self.append(header, data).await
+    }
+
+    /// Adds a new link (symbolic or hard) entry to this archive with the
+    /// specified path and target.
+    ///
+    /// This behaves like [`Builder::append_link`](crate::Builder::append_link).
+    pub async fn append_link<P: AsRef<Path>, T: AsRef<Path>>(
+        &mut self,
+        header: &mut Header,
+        path: P,
+        target: T,
+    ) -> io::Result<()> {
+        self.write_headers(header, path.as_ref(), Some(target.as_ref()))
+            .await?;
+        self.append(header, tokio::io::empty()).await
+    }
+
+    async fn write_headers(
+        &mut self,
+        header: &mut Header,
+        path: &Path,
+        link_name: Option<&Path>,
+    ) -> io::Result<()> {
+        let mut headers = Vec::new();
+        prepare_headers(&mut headers, header, path, link_name, &self.options)?;
+        self.obj.write_all(&headers).await
+    }
+
+    /// Finish writing this archive, emitting the termination sections.
+    ///
+    /// This function should only be called when the archive has been written
+    /// entirely and if an I/O error happens the underlying object still needs
+    /// to be acquired.
+    ///
+    /// In most situations the `into_inner` method should be preferred.
+    pub async fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.obj.write_all(&[0; 1024]).await?;
+        self.obj.flush().await
+    }
+}
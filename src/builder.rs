@@ -1,14 +1,25 @@
+use std::cmp;
+use std::collections::HashMap;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str;
 
+use crate::archive::try_read_all;
+use crate::decoder::{Decoded, Decoder};
+use crate::digest::{Digests, EntryDigest};
+use crate::entry::{EntryFields, StoredData};
 use crate::header::BLOCK_SIZE;
 use crate::header::GNU_SPARSE_HEADERS_COUNT;
 use crate::header::{path2bytes, HeaderMode};
+use crate::pax::{
+    format_pax_time, PaxBuilder, PAX_ATIME, PAX_CTIME, PAX_GID, PAX_GNAME, PAX_GNUSPARSE,
+    PAX_GNUSPARSEMAJOR, PAX_GNUSPARSEMINOR, PAX_GNUSPARSENAME, PAX_GNUSPARSEREALSIZE, PAX_LINKPATH,
+    PAX_MTIME, PAX_PATH, PAX_SIZE, PAX_UID, PAX_UNAME,
+};
 use crate::GnuExtSparseHeader;
-use crate::{other, EntryType, Header};
+use crate::{other, Digest, Entry, EntryType, Header, IdMap, PaxExtensions};
 
 /// A structure for building archives
 ///
@@ -16,16 +27,124 @@ use crate::{other, EntryType, Header};
 /// arbitrary writer.
 pub struct Builder<W: Write> {
     options: BuilderOptions,
+    links: HardLinks,
     finished: bool,
+    digests: Option<Digests>,
     obj: Option<W>,
 }
 
-#[derive(Clone, Copy)]
-struct BuilderOptions {
+/// The paths under which files with several hard links were first archived,
+/// by device and inode number.
+type HardLinks = HashMap<(u64, u64), PathBuf>;
+
+#[derive(Clone)]
+pub(crate) struct BuilderOptions {
     mode: HeaderMode,
+    pub(crate) format: ArchiveFormat,
     preserve_absolute: bool,
     follow: bool,
     sparse: bool,
+    sort: bool,
+    hard_links: bool,
+    pax_timestamps: bool,
+    uid_map: Option<IdMap>,
+    gid_map: Option<IdMap>,
+    xattrs: Option<XattrFilter>,
+    acls: bool,
+}
+
+impl Default for BuilderOptions {
+    fn default() -> BuilderOptions {
+        BuilderOptions {
+            mode: HeaderMode::Complete,
+            format: ArchiveFormat::Gnu,
+            preserve_absolute: false,
+            follow: true,
+            sparse: true,
+            sort: false,
+            hard_links: true,
+            pax_timestamps: false,
+            uid_map: None,
+            gid_map: None,
+            xattrs: None,
+            acls: false,
+        }
+    }
+}
+
+impl BuilderOptions {
+    fn new_header(&self) -> Header {
+        match self.format {
+            ArchiveFormat::Gnu => Header::new_gnu(),
+            ArchiveFormat::Pax => Header::new_ustar(),
+        }
+    }
+}
+
+/// The flavor of tar archive produced by a [`Builder`].
+///
+/// This determines both the kind of header written for entries created from
+/// the filesystem and how paths and link names which don't fit in a header are
+/// recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ArchiveFormat {
+    /// GNU headers, with long paths and link names stored in GNU `L`/`K`
+    /// extension entries. This is the default.
+    Gnu,
+    /// POSIX.1-2001 (pax) archives: ustar headers, with long paths and link
+    /// names stored as `path`/`linkpath` records of a pax extended header.
+    Pax,
+}
+
+/// Chooses which extended attributes are captured by a [`Builder`], by the
+/// prefixes of their names.
+///
+/// An attribute is captured if its name starts with one of the included
+/// prefixes, or if none are included, and doesn't start with any of the
+/// excluded ones. A new filter captures all attributes.
+///
+/// # Examples
+///
+/// ```
+/// use tar::XattrFilter;
+///
+/// let mut filter = XattrFilter::new();
+/// filter.exclude("security.selinux");
+/// assert!(filter.matches("user.comment"));
+/// assert!(!filter.matches("security.selinux"));
+///
+/// filter.include("user.");
+/// assert!(!filter.matches("trusted.overlay.opaque"));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct XattrFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl XattrFilter {
+    /// Creates a filter which captures all attributes.
+    pub fn new() -> XattrFilter {
+        XattrFilter::default()
+    }
+
+    /// Only captures attributes whose names start with `prefix`, or with
+    /// another included prefix, such as `user.` for a whole namespace.
+    pub fn include(&mut self, prefix: &str) {
+        self.include.push(prefix.to_string());
+    }
+
+    /// Never captures attributes whose names start with `prefix`.
+    pub fn exclude(&mut self, prefix: &str) {
+        self.exclude.push(prefix.to_string());
+    }
+
+    /// Returns whether the attribute called `name` is captured.
+    pub fn matches(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| name.starts_with(&**p)))
+            && !self.exclude.iter().any(|p| name.starts_with(&**p))
+    }
 }
 
 impl<W: Write> Builder<W> {
@@ -34,13 +153,10 @@ impl<W: Write> Builder<W> {
     /// `HeaderMode::Complete` by default.
     pub fn new(obj: W) -> Builder<W> {
         Builder {
-            options: BuilderOptions {
-                mode: HeaderMode::Complete,
-                preserve_absolute: false,
-                follow: true,
-                sparse: true,
-            },
+            options: BuilderOptions::default(),
+            links: HashMap::new(),
             finished: false,
+            digests: None,
             obj: Some(obj),
         }
     }
@@ -52,6 +168,23 @@ impl<W: Write> Builder<W> {
         self.options.mode = mode;
     }
 
+    /// Changes the format of the archive being produced. Defaults to
+    /// `ArchiveFormat::Gnu`.
+    ///
+    /// With `ArchiveFormat::Pax` entries read from the filesystem get ustar
+    /// headers, and paths or link names too long for the header are written
+    /// as pax extended header records rather than GNU extension entries.
+    /// Likewise a uid, gid, size or mtime too large for the octal fields of a
+    /// ustar header is written as a pax record, with zero in the header,
+    /// instead of GNU's base-256 encoding. Sparse files are written in the pax
+    /// 1.0 sparse format.
+    ///
+    /// Note that this does _not_ change the kind of header passed to methods
+    /// like `append_data`, only how overlong paths for them are encoded.
+    pub fn format(&mut self, format: ArchiveFormat) {
+        self.options.format = format;
+    }
+
     /// Peserve absolute path while creating an archive
     pub fn preserve_absolute(&mut self, preserve: bool) {
         self.options.preserve_absolute = preserve;
@@ -68,11 +201,134 @@ impl<W: Write> Builder<W> {
 
     /// Handle sparse files efficiently, if supported by the underlying
     /// filesystem. When true, sparse file information is read from disk and
-    /// empty segments are omitted from the archive. Defaults to true.
+    /// empty segments are omitted from the archive. Sparse files are written
+    /// with the old GNU sparse headers in `ArchiveFormat::Gnu` archives, and
+    /// in the pax 1.0 sparse format in `ArchiveFormat::Pax` archives.
+    /// Defaults to true.
     pub fn sparse(&mut self, sparse: bool) {
         self.options.sparse = sparse;
     }
 
+    /// Add the contents of directories walked by `append_dir_all` in order of
+    /// their byte-wise names, rather than in the order the filesystem lists
+    /// them. Defaults to false.
+    ///
+    /// Each directory is always added before its contents, so together with
+    /// `HeaderMode::Deterministic` this produces the same archive for the same
+    /// tree on any machine.
+    pub fn sort_by_name(&mut self, sort: bool) {
+        self.options.sort = sort;
+    }
+
+    /// Detect files read from the filesystem which are hard links to a file
+    /// already added to the archive, and add them as `EntryType::Link` entries
+    /// pointing at the earlier path rather than as copies of the file's data.
+    /// Defaults to true.
+    ///
+    /// Files are identified by their device and inode number, so this is
+    /// currently only implemented on Unix.
+    pub fn detect_hard_links(&mut self, detect: bool) {
+        self.options.hard_links = detect;
+    }
+
+    /// Record the modification, access and status change times of files read
+    /// from the filesystem as pax extended header records, keeping their
+    /// sub-second precision. Defaults to false.
+    ///
+    /// The header's `mtime` field is still filled in with whole seconds for
+    /// readers which don't understand pax records. This only applies in
+    /// `HeaderMode::Complete`.
+    pub fn pax_timestamps(&mut self, pax_timestamps: bool) {
+        self.options.pax_timestamps = pax_timestamps;
+    }
+
+    /// Translate the uids of files read from the filesystem to the uids to
+    /// record in the archive, for example when packing a container's root
+    /// filesystem whose files are owned by shifted subordinate ids.
+    ///
+    /// Files owned by a uid which isn't mapped can't be appended. By default
+    /// uids aren't translated. This only applies in `HeaderMode::Complete`.
+    pub fn uid_map(&mut self, map: IdMap) {
+        self.options.uid_map = Some(map);
+    }
+
+    /// Translate the gids of files read from the filesystem to the gids to
+    /// record in the archive.
+    ///
+    /// This is the group counterpart of `uid_map`.
+    pub fn gid_map(&mut self, map: IdMap) {
+        self.options.gid_map = Some(map);
+    }
+
+    /// Capture the extended attributes of files added from the filesystem
+    /// which `filter` matches, as `SCHILY.xattr.*` records of a pax extended
+    /// header before each entry.
+    ///
+    /// This is the counterpart of `Archive::set_unpack_xattrs`. Attributes are
+    /// only captured on Unix with the `xattr` feature enabled, and are
+    /// otherwise ignored. By default no attributes are captured.
+    ///
+    /// Like GNU tar, the `system.posix_acl_*` attributes holding POSIX ACLs
+    /// are never captured, whatever the filter: ACLs are captured with `acls`
+    /// instead, which maps their ids. Attributes whose names aren't valid
+    /// UTF-8 can't be stored in pax records and are skipped as well.
+    pub fn xattrs(&mut self, filter: XattrFilter) {
+        self.options.xattrs = Some(filter);
+    }
+
+    /// Capture the POSIX ACLs of files added from the filesystem as
+    /// `SCHILY.acl.access` records, and the default ACLs of directories as
+    /// `SCHILY.acl.default` records, in the format of GNU tar.
+    ///
+    /// Named users and groups are recorded by their ids, which are mapped
+    /// with `Builder::uid_map` and `Builder::gid_map`. This is the
+    /// counterpart of `Archive::set_unpack_acls`. ACLs are only captured on
+    /// Linux with the `acl` feature enabled, and are otherwise ignored. This
+    /// is disabled by default.
+    pub fn acls(&mut self, acls: bool) {
+        self.options.acls = acls;
+    }
+
+    /// Computes digests of the contents of each entry, and of the whole
+    /// archive, with the hash function `digest` as the archive is written.
+    ///
+    /// The digests cover everything written after this is called, so it's
+    /// usually called before adding any entries. The digest of each entry is
+    /// of its contents, with the holes of sparse files as zeros, and is
+    /// returned by `take_entry_digests` once the entry is written. The digest
+    /// of the archive is available from `archive_digest`.
+    ///
+    /// The archive is decoded as it's written to find the contents of each
+    /// entry, so writing an entry fails if its header isn't valid. The header
+    /// which is rejected isn't written, but extension headers describing it
+    /// may already have been, so the archive can't be used after such an
+    /// error. Entries can't be added with `append_writer` while digests are
+    /// being computed, as it writes the header of an entry after its
+    /// contents.
+    pub fn digest<D: Digest + 'static>(&mut self, digest: D) {
+        self.digests = Some(Digests::new(Box::new(digest), true));
+    }
+
+    /// Returns the digests of the entries written since this was last called,
+    /// in order, if digests are being computed.
+    ///
+    /// Digests are kept until they're taken, so this should be called from
+    /// time to time when writing many entries.
+    pub fn take_entry_digests(&mut self) -> Vec<EntryDigest> {
+        self.digests
+            .as_mut()
+            .map(|d| d.take_entry_digests())
+            .unwrap_or_default()
+    }
+
+    /// Returns the digest of everything written to the archive so far, if
+    /// digests are being computed.
+    ///
+    /// This is the digest of the whole archive once `finish` has been called.
+    pub fn archive_digest(&self) -> Option<Vec<u8>> {
+        self.digests.as_ref().map(|d| d.archive_digest())
+    }
+
     /// Gets shared reference to the underlying object.
     pub fn get_ref(&self) -> &W {
         self.obj.as_ref().unwrap()
@@ -137,13 +393,18 @@ impl<W: Write> Builder<W> {
     /// let data = ar.into_inner().unwrap();
     /// ```
     pub fn append<R: Read>(&mut self, header: &Header, mut data: R) -> io::Result<()> {
-        append(self.get_mut(), header, &mut data)
+        append(
+            &mut output(&mut self.obj, &mut self.digests),
+            header,
+            &mut data,
+        )
     }
 
     /// Adds a new entry to this archive with the specified path.
     ///
     /// This function will set the specified path in the given header, which may
-    /// require appending a GNU long-name extension entry to the archive first.
+    /// require appending a GNU long-name extension entry (or a pax extended
+    /// header, depending on the archive format) to the archive first.
     /// The checksum for the header will be automatically updated via the
     /// `set_cksum` method after setting the path. No other metadata in the
     /// header will be modified.
@@ -186,9 +447,8 @@ impl<W: Write> Builder<W> {
         path: P,
         data: R,
     ) -> io::Result<()> {
-        let allow_absolute = self.options.preserve_absolute;
-        prepare_header_path(self.get_mut(), header, path.as_ref(), allow_absolute)?;
-        header.set_cksum();
+        let dst = &mut output(&mut self.obj, &mut self.digests);
+        prepare_headers(dst, header, path.as_ref(), None, &self.options)?;
         self.append(header, data)
     }
 
@@ -230,14 +490,20 @@ impl<W: Write> Builder<W> {
     where
         W: Seek,
     {
-        let allow_absolute = self.options.preserve_absolute;
-        EntryWriter::start(self.get_mut(), header, path.as_ref(), allow_absolute)
+        if self.digests.is_some() {
+            return Err(other(
+                "cannot add entries with append_writer while computing digests",
+            ));
+        }
+        let dst = self.obj.as_mut().unwrap();
+        EntryWriter::start(dst, header, path.as_ref(), &self.options)
     }
 
     /// Adds a new link (symbolic or hard) entry to this archive with the specified path and target.
     ///
     /// This function is similar to [`Self::append_data`] which supports long filenames,
-    /// but also supports long link targets using GNU extensions if necessary.
+    /// but also supports long link targets using GNU extensions (or pax extended
+    /// headers, depending on the archive format) if necessary.
     /// You must set the entry type to either [`EntryType::Link`] or [`EntryType::Symlink`].
     /// The `set_cksum` method will be invoked after setting the path. No other metadata in the
     /// header will be modified.
@@ -276,13 +542,144 @@ impl<W: Write> Builder<W> {
     }
 
     fn _append_link(&mut self, header: &mut Header, path: &Path, target: &Path) -> io::Result<()> {
-        let allow_abolute = self.options.preserve_absolute;
-        prepare_header_path(self.get_mut(), header, path, allow_abolute)?;
-        prepare_header_link(self.get_mut(), header, target)?;
-        header.set_cksum();
+        let dst = &mut output(&mut self.obj, &mut self.digests);
+        prepare_headers(dst, header, path, Some(target), &self.options)?;
         self.append(header, std::io::empty())
     }
 
+    /// Copies an entry read from another archive into this archive.
+    ///
+    /// The entry is written as it was read: its header, path and link name
+    /// (however long), pax extensions and the layout of sparse files are all
+    /// carried over, along with its contents. The contents must not have been
+    /// read yet.
+    ///
+    /// Only the entry's own pax records are copied. Pax global extended
+    /// headers are entries of their own, and are copied when they're appended
+    /// like any other entry.
+    ///
+    /// Long paths and link names are written as pax records if the entry had
+    /// pax extensions, and following `format` otherwise. Sparse files keep the
+    /// old GNU format if they used it, and are otherwise written in the pax
+    /// 1.0 sparse format.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Archive, Builder};
+    ///
+    /// let mut src = Archive::new(File::open("foo.tar").unwrap());
+    /// let mut ar = Builder::new(File::create("bar.tar").unwrap());
+    /// for entry in src.entries().unwrap() {
+    ///     let mut entry = entry.unwrap();
+    ///     if entry.path().unwrap().ends_with(".git") {
+    ///         continue;
+    ///     }
+    ///     ar.append_entry(&mut entry).unwrap();
+    /// }
+    /// ar.finish().unwrap();
+    /// ```
+    pub fn append_entry<R: Read>(&mut self, entry: &mut Entry<'_, R>) -> io::Result<()> {
+        self.append_entry_with(entry, |_, _| Ok(()))
+    }
+
+    /// Copies an entry read from another archive into this archive, first
+    /// passing its path and header to `modify`.
+    ///
+    /// This behaves like `append_entry`, except that `modify` can rename the
+    /// entry or change its header. Fields of the header which `modify`
+    /// changes take precedence over the pax records for them, so that for
+    /// example resetting the mtime drops any `mtime` record. The size of the
+    /// header is always set from the entry.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Archive, Builder};
+    ///
+    /// let mut src = Archive::new(File::open("foo.tar").unwrap());
+    /// let mut ar = Builder::new(File::create("bar.tar").unwrap());
+    /// for entry in src.entries().unwrap() {
+    ///     ar.append_entry_with(&mut entry.unwrap(), |path, header| {
+    ///         *path = std::path::Path::new("prefix").join(&*path);
+    ///         header.set_mtime(0);
+    ///         header.set_uid(0);
+    ///         header.set_gid(0);
+    ///         Ok(())
+    ///     })
+    ///     .unwrap();
+    /// }
+    /// ar.finish().unwrap();
+    /// ```
+    pub fn append_entry_with<R, F>(&mut self, entry: &mut Entry<'_, R>, modify: F) -> io::Result<()>
+    where
+        R: Read,
+        F: FnOnce(&mut PathBuf, &mut Header) -> io::Result<()>,
+    {
+        let mut path = entry.path()?.into_owned();
+        let mut header = entry.header().clone();
+        modify(&mut path, &mut header)?;
+        let dst = &mut output(&mut self.obj, &mut self.digests);
+        append_entry(dst, entry.fields_mut(), &path, header, &self.options)
+    }
+
+    /// Adds a sparse file to this archive, of which only the given regions
+    /// contain data.
+    ///
+    /// `regions` lists the `(offset, length)` of each region of data, in
+    /// order, and `data` is the whole contents of the file, whose size is
+    /// taken from its end. Only the regions are read from it and stored in the
+    /// archive, and the rest of the file reads back as zeros.
+    ///
+    /// With `ArchiveFormat::Gnu` the old GNU sparse format is used, which
+    /// requires `header` to be a GNU header and every region but the last to
+    /// be a multiple of 512 bytes long. With `ArchiveFormat::Pax` the
+    /// file is written in the pax 1.0 sparse format. The size and entry type
+    /// of `header` are set by this method, and its checksum updated.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use tar::{Builder, Header};
+    ///
+    /// // A 1MiB disk image with data only at the start and the end.
+    /// let mut image = vec![0; 1 << 20];
+    /// image[..512].fill(1);
+    /// image[(1 << 20) - 512..].fill(2);
+    ///
+    /// let mut header = Header::new_gnu();
+    /// header.set_mode(0o644);
+    /// let mut ar = Builder::new(Vec::new());
+    /// let regions = [(0, 512), ((1 << 20) - 512, 512)];
+    /// ar.append_sparse(&mut header, "disk.img", &regions, Cursor::new(&image))
+    ///     .unwrap();
+    /// let data = ar.into_inner().unwrap();
+    /// assert!(data.len() < 4096);
+    /// ```
+    pub fn append_sparse<P: AsRef<Path>, R: Read + Seek>(
+        &mut self,
+        header: &mut Header,
+        path: P,
+        regions: &[(u64, u64)],
+        mut data: R,
+    ) -> io::Result<()> {
+        let size = data.seek(io::SeekFrom::End(0))?;
+        let mut data = Regions {
+            obj: data,
+            regions: regions.iter(),
+            remaining: 0,
+        };
+        let format = self.options.format;
+        check_sparse(header, regions, size, format)?;
+        let dst = &mut output(&mut self.obj, &mut self.digests);
+        let mut pax = PaxBuilder::new();
+        prepare_sparse_path(dst, header, path.as_ref(), &self.options, &mut pax, format)?;
+        append_sparse(dst, header, pax, regions, size, format, &mut data)
+    }
+
     /// Adds a file on the local filesystem to this archive.
     ///
     /// This function will open the file specified by `path` and insert the file
@@ -308,8 +705,8 @@ impl<W: Write> Builder<W> {
     /// ar.append_path("foo/bar.txt").unwrap();
     /// ```
     pub fn append_path<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
-        let options = self.options;
-        append_path_with_name(self.get_mut(), path.as_ref(), None, options)
+        let dst = &mut output(&mut self.obj, &mut self.digests);
+        append_path_with_name(dst, path.as_ref(), None, &self.options, &mut self.links)
     }
 
     /// Adds a file on the local filesystem to this archive under another name.
@@ -345,8 +742,14 @@ impl<W: Write> Builder<W> {
         path: P,
         name: N,
     ) -> io::Result<()> {
-        let options = self.options;
-        append_path_with_name(self.get_mut(), path.as_ref(), Some(name.as_ref()), options)
+        let dst = &mut output(&mut self.obj, &mut self.digests);
+        append_path_with_name(
+            dst,
+            path.as_ref(),
+            Some(name.as_ref()),
+            &self.options,
+            &mut self.links,
+        )
     }
 
     /// Adds a file to this archive with the given path as the name of the file
@@ -376,8 +779,8 @@ impl<W: Write> Builder<W> {
     /// ar.append_file("bar/baz.txt", &mut f).unwrap();
     /// ```
     pub fn append_file<P: AsRef<Path>>(&mut self, path: P, file: &mut fs::File) -> io::Result<()> {
-        let options = self.options;
-        append_file(self.get_mut(), path.as_ref(), file, options)
+        let dst = &mut output(&mut self.obj, &mut self.digests);
+        append_file(dst, path.as_ref(), file, &self.options, &mut self.links)
     }
 
     /// Adds a directory to this archive with the given path as the name of the
@@ -413,8 +816,8 @@ impl<W: Write> Builder<W> {
         P: AsRef<Path>,
         Q: AsRef<Path>,
     {
-        let options = self.options;
-        append_dir(self.get_mut(), path.as_ref(), src_path.as_ref(), options)
+        let dst = &mut output(&mut self.obj, &mut self.digests);
+        append_dir(dst, path.as_ref(), src_path.as_ref(), &self.options)
     }
 
     /// Adds a directory and all of its contents (recursively) to this archive
@@ -475,8 +878,63 @@ impl<W: Write> Builder<W> {
         P: AsRef<Path>,
         Q: AsRef<Path>,
     {
-        let options = self.options;
-        append_dir_all(self.get_mut(), path.as_ref(), src_path.as_ref(), options)
+        let dst = &mut output(&mut self.obj, &mut self.digests);
+        append_dir_all(
+            dst,
+            path.as_ref(),
+            src_path.as_ref(),
+            &self.options,
+            &mut self.links,
+            &mut |_, _| true,
+        )
+    }
+
+    /// Adds a directory and the contents of it selected by `filter`
+    /// (recursively) to this archive with the given path as the name of the
+    /// directory in the archive.
+    ///
+    /// The `filter` is called for everything found below `src_path` with its
+    /// path relative to `src_path` and its metadata, as from
+    /// `fs::symlink_metadata`, so symlinks are described rather than their
+    /// targets even when they're followed. Returning `false` leaves the file
+    /// out of the archive, and for a directory also skips everything inside
+    /// of it. Otherwise this behaves like `append_dir_all`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tar::Builder;
+    ///
+    /// let mut ar = Builder::new(Vec::new());
+    ///
+    /// // Archive the current directory, except for version control and build
+    /// // output directories.
+    /// ar.append_dir_all_with("bardir", ".", |path, meta| {
+    ///     !(meta.is_dir() && (path.ends_with(".git") || path.ends_with("target")))
+    /// })
+    /// .unwrap();
+    /// ar.finish().unwrap();
+    /// ```
+    pub fn append_dir_all_with<P, Q, F>(
+        &mut self,
+        path: P,
+        src_path: Q,
+        mut filter: F,
+    ) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+        F: FnMut(&Path, &fs::Metadata) -> bool,
+    {
+        let dst = &mut output(&mut self.obj, &mut self.digests);
+        append_dir_all(
+            dst,
+            path.as_ref(),
+            src_path.as_ref(),
+            &self.options,
+            &mut self.links,
+            &mut filter,
+        )
     }
 
     /// Finish writing this archive, emitting the termination sections.
@@ -491,7 +949,94 @@ impl<W: Write> Builder<W> {
             return Ok(());
         }
         self.finished = true;
-        self.get_mut().write_all(&[0; 1024])
+        output(&mut self.obj, &mut self.digests).write_all(&[0; 1024])
+    }
+}
+
+impl<W: Read + Write + Seek> Builder<W> {
+    /// Opens an existing archive to append more entries to it, like `tar -r`.
+    ///
+    /// The archive is read from the current position of `obj` to find where
+    /// its last entry ends, and new entries overwrite the blocks of zeros which
+    /// marked its end from there. The archive must be finished again with
+    /// `finish` or `into_inner` once all entries are added.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::OpenOptions;
+    /// use tar::Builder;
+    ///
+    /// let file = OpenOptions::new()
+    ///     .read(true)
+    ///     .write(true)
+    ///     .open("foo.tar")
+    ///     .unwrap();
+    /// let mut ar = Builder::open_append(file).unwrap();
+    /// ar.append_path("file1.txt").unwrap();
+    /// ar.finish().unwrap();
+    /// ```
+    pub fn open_append(mut obj: W) -> io::Result<Builder<W>> {
+        let start = obj.stream_position()?;
+        let end = find_end(&mut obj, start)?;
+        obj.seek(io::SeekFrom::Start(start + end))?;
+        Ok(Builder::new(obj))
+    }
+}
+
+/// Returns the offset, relative to `start`, just past the last entry of the
+/// archive in `obj`.
+fn find_end<R: Read + Seek>(obj: &mut R, start: u64) -> io::Result<u64> {
+    let mut decoder = Decoder::new();
+    let mut block = [0; BLOCK_SIZE as usize];
+    let mut pos = 0;
+    loop {
+        let end = decoder.position();
+        if end != pos {
+            obj.seek(io::SeekFrom::Start(start + end))?;
+        }
+        let decoded = if try_read_all(obj, &mut block)? {
+            pos = end + BLOCK_SIZE;
+            decoder.decode(&block)?.1
+        } else {
+            pos = end;
+            Some(decoder.finish()?)
+        };
+        match decoded {
+            Some(Decoded::Entry(_)) => decoder.skip_data(),
+            Some(Decoded::End) => return Ok(end),
+            _ => {}
+        }
+    }
+}
+
+/// Returns the writer which entries are written to, which also hashes
+/// everything written when digests are being computed.
+fn output<'a, W: Write>(obj: &'a mut Option<W>, digests: &'a mut Option<Digests>) -> Output<'a> {
+    Output {
+        obj: obj.as_mut().unwrap(),
+        digests: digests.as_mut(),
+    }
+}
+
+struct Output<'a> {
+    obj: &'a mut dyn Write,
+    digests: Option<&'a mut Digests>,
+}
+
+impl Write for Output<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Bytes are decoded before they're written, so that what's written
+        // is never rejected afterwards.
+        if let Some(digests) = &mut self.digests {
+            digests.update(buf)?;
+        }
+        self.obj.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.obj.flush()
     }
 }
 
@@ -525,9 +1070,9 @@ impl EntryWriter<'_> {
         obj: &'a mut dyn SeekWrite,
         header: &'a mut Header,
         path: &Path,
-        allow_absolute: bool,
+        options: &BuilderOptions,
     ) -> io::Result<EntryWriter<'a>> {
-        prepare_header_path(obj.as_write(), header, path, allow_absolute)?;
+        prepare_headers(obj.as_write(), header, path, None, options)?;
 
         // Reserve space for header, will be overwritten once data is written.
         obj.write_all([0u8; BLOCK_SIZE as usize].as_ref())?;
@@ -601,6 +1146,35 @@ fn append(mut dst: &mut dyn Write, header: &Header, mut data: &mut dyn Read) ->
     Ok(())
 }
 
+/// Sets the path and link name of `header`, writing any long name or pax
+/// extension entries needed for them, and updates its checksum. The header
+/// itself is left to the caller to write.
+pub(crate) fn prepare_headers(
+    dst: &mut dyn Write,
+    header: &mut Header,
+    path: &Path,
+    link_name: Option<&Path>,
+    options: &BuilderOptions,
+) -> io::Result<()> {
+    let mut pax = PaxBuilder::new();
+    prepare_header_path(dst, header, path, options, &mut pax)?;
+    if let Some(link_name) = link_name {
+        prepare_header_link(dst, header, link_name, options, &mut pax)?;
+    }
+    prepare_pax_numbers(header, options.format, &mut pax)?;
+    append_pax(dst, &pax)?;
+    header.set_cksum();
+    Ok(())
+}
+
+/// Writes the pax extended header accumulated for the next entry, if any.
+fn append_pax(dst: &mut dyn Write, pax: &PaxBuilder) -> io::Result<()> {
+    if pax.is_empty() {
+        return Ok(());
+    }
+    append(dst, &pax.header(), &mut pax.as_bytes())
+}
+
 fn pad_zeroes(dst: &mut dyn Write, len: u64) -> io::Result<()> {
     let buf = [0; BLOCK_SIZE as usize];
     let remaining = BLOCK_SIZE - (len % BLOCK_SIZE);
@@ -614,7 +1188,8 @@ fn append_path_with_name(
     dst: &mut dyn Write,
     path: &Path,
     name: Option<&Path>,
-    options: BuilderOptions,
+    options: &BuilderOptions,
+    links: &mut HardLinks,
 ) -> io::Result<()> {
     let stat = if options.follow {
         fs::metadata(path).map_err(|err| {
@@ -633,30 +1208,16 @@ fn append_path_with_name(
     };
     let ar_name = name.unwrap_or(path);
     if stat.is_file() {
-        append_file(dst, ar_name, &mut fs::File::open(path)?, options)
+        append_file(dst, ar_name, &mut fs::File::open(path)?, options, links)
     } else if stat.is_dir() {
-        append_fs(
-            dst,
-            ar_name,
-            &stat,
-            options.mode,
-            options.preserve_absolute,
-            None,
-        )
+        append_fs(dst, ar_name, path, &stat, options, None)
     } else if stat.file_type().is_symlink() {
         let link_name = fs::read_link(path)?;
-        append_fs(
-            dst,
-            ar_name,
-            &stat,
-            options.mode,
-            options.preserve_absolute,
-            Some(&link_name),
-        )
+        append_fs(dst, ar_name, path, &stat, options, Some(&link_name))
     } else {
         #[cfg(unix)]
         {
-            append_special(dst, path, &stat, options.mode, options.preserve_absolute)
+            append_special(dst, ar_name, path, &stat, options, links)
         }
         #[cfg(not(unix))]
         {
@@ -665,16 +1226,301 @@ fn append_path_with_name(
     }
 }
 
+fn append_entry(
+    dst: &mut dyn Write,
+    entry: &mut EntryFields<'_>,
+    path: &Path,
+    mut header: Header,
+    options: &BuilderOptions,
+) -> io::Result<()> {
+    if !entry.is_unread() {
+        return Err(other(&format!(
+            "cannot copy entry {} after reading its contents",
+            path.display()
+        )));
+    }
+    let rename = *path2bytes(path)? != *entry.path_bytes();
+    let blocks = entry.sparse.take();
+    let format = if entry.header.entry_type().is_gnu_sparse() && header.as_gnu().is_some() {
+        ArchiveFormat::Gnu
+    } else {
+        ArchiveFormat::Pax
+    };
+    if let Some(blocks) = &blocks {
+        check_sparse(&header, blocks, entry.size, format)?;
+    }
+
+    // Carry over the pax records, except for those written afresh below and
+    // those for fields of the header which were changed.
+    let original = &entry.header;
+    let mut pax = PaxBuilder::new();
+    if let Some(extensions) = &entry.pax_extensions {
+        for extension in PaxExtensions::new(extensions) {
+            let extension = extension?;
+            let key = extension
+                .key()
+                .map_err(|_| other("pax extension key is not valid UTF-8"))?;
+            let replaced = match key {
+                PAX_PATH | PAX_GNUSPARSENAME => rename,
+                PAX_SIZE => true,
+                PAX_MTIME => header.as_old().mtime != original.as_old().mtime,
+                PAX_UID => header.as_old().uid != original.as_old().uid,
+                PAX_GID => header.as_old().gid != original.as_old().gid,
+                PAX_UNAME => header.username_bytes() != original.username_bytes(),
+                PAX_GNAME => header.groupname_bytes() != original.groupname_bytes(),
+                _ => key.starts_with(PAX_GNUSPARSE),
+            };
+            if !replaced {
+                pax.add(key, extension.value_bytes());
+            }
+        }
+    }
+    // Fields of the header which were set from pax records, global ones
+    // included, are copied as they were read unless they were changed.
+    let raw = entry.raw_header.as_old();
+    let old = header.as_old_mut();
+    if old.uid == original.as_old().uid {
+        old.uid = raw.uid;
+    }
+    if old.gid == original.as_old().gid {
+        old.gid = raw.gid;
+    }
+    if old.mtime == original.as_old().mtime {
+        old.mtime = raw.mtime;
+    }
+    let mut options = options.clone();
+    if entry.pax_extensions.is_some() {
+        options.format = ArchiveFormat::Pax;
+    }
+
+    // Names are copied byte for byte unless the entry was renamed.
+    if !rename {
+        if let Some(name) = &entry.long_pathname {
+            append_long_name(dst, b'L', name)?;
+        }
+    } else if blocks.is_some() {
+        prepare_sparse_path(dst, &mut header, path, &options, &mut pax, format)?;
+    } else {
+        prepare_header_path(dst, &mut header, path, &options, &mut pax)?;
+    }
+    if let Some(name) = &entry.long_linkname {
+        append_long_name(dst, b'K', name)?;
+    }
+
+    let size = entry.size;
+    let mut data = StoredData(entry);
+    match blocks {
+        Some(blocks) => append_sparse(dst, &mut header, pax, &blocks, size, format, &mut data),
+        None => {
+            header.set_size(size);
+            append_pax(dst, &pax)?;
+            header.set_cksum();
+            dst.write_all(header.as_bytes())?;
+            copy_exact(&mut data, dst, size)
+        }
+    }
+}
+
+/// Writes a GNU long name or long link entry for `name`.
+fn append_long_name(dst: &mut dyn Write, entry_type: u8, name: &[u8]) -> io::Result<()> {
+    let name = name.strip_suffix(&[0]).unwrap_or(name);
+    let header = prepare_header(name.len() as u64, entry_type);
+    // null-terminated string
+    append(dst, &header, &mut name.chain(io::repeat(0).take(1)))
+}
+
+/// Checks that the `(offset, length)` blocks of a sparse file of `size` bytes
+/// are in order and that `header` can describe them in `format`.
+fn check_sparse(
+    header: &Header,
+    blocks: &[(u64, u64)],
+    size: u64,
+    format: ArchiveFormat,
+) -> io::Result<()> {
+    if format == ArchiveFormat::Gnu && header.as_gnu().is_none() {
+        return Err(other("GNU sparse files need a GNU header"));
+    }
+    let mut cur = 0;
+    let mut stored = 0;
+    for &(off, len) in blocks {
+        if off < cur {
+            return Err(other("out of order or overlapping sparse blocks"));
+        }
+        // The data of each block of a GNU sparse file starts on a 512-byte
+        // boundary in the archive.
+        if format == ArchiveFormat::Gnu && len != 0 && stored % BLOCK_SIZE != 0 {
+            return Err(other(
+                "blocks of GNU sparse files other than the last \
+                 must be a multiple of 512 bytes long",
+            ));
+        }
+        stored += len;
+        cur = off
+            .checked_add(len)
+            .ok_or_else(|| other("more bytes listed in sparse file than u64 can hold"))?;
+    }
+    if cur > size {
+        return Err(other("sparse blocks extend past the end of the file"));
+    }
+    Ok(())
+}
+
+/// Sets the path of a sparse file written in the given format.
+///
+/// Pax 1.0 sparse files record their path as a pax record, and the header
+/// gets a placeholder path which is only seen by tools which don't know how to
+/// expand them.
+fn prepare_sparse_path(
+    dst: &mut dyn Write,
+    header: &mut Header,
+    path: &Path,
+    options: &BuilderOptions,
+    pax: &mut PaxBuilder,
+    format: ArchiveFormat,
+) -> io::Result<()> {
+    match format {
+        ArchiveFormat::Gnu => prepare_header_path(dst, header, path, options, pax),
+        ArchiveFormat::Pax => {
+            pax.add(PAX_GNUSPARSENAME, &path2bytes(path)?);
+            let name = path.file_name().unwrap_or(path.as_os_str());
+            let placeholder = path
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join("GNUSparseFile.0")
+                .join(name);
+            prepare_header_path(dst, header, &placeholder, options, pax)
+        }
+    }
+}
+
+/// Reads the given `(offset, length)` regions of `obj` back to back.
+struct Regions<'a, R> {
+    obj: R,
+    regions: std::slice::Iter<'a, (u64, u64)>,
+    remaining: u64,
+}
+
+impl<R: Read + Seek> Read for Regions<'_, R> {
+    fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
+        while self.remaining == 0 {
+            match self.regions.next() {
+                Some(&(off, len)) => {
+                    self.obj.seek(io::SeekFrom::Start(off))?;
+                    self.remaining = len;
+                }
+                None => return Ok(0),
+            }
+        }
+        let len = cmp::min(into.len() as u64, self.remaining) as usize;
+        let n = self.obj.read(&mut into[..len])?;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// Writes a sparse file of `size` bytes, whose `(offset, length)` blocks of
+/// data are read back to back from `data`. The path of `header` and the pax
+/// records naming the file must already be set.
+///
+/// With `ArchiveFormat::Gnu` this uses the old GNU sparse headers, which
+/// requires `header` to be a GNU header, and otherwise the pax 1.0 sparse
+/// format. The blocks must already have been checked with `check_sparse`.
+fn append_sparse(
+    dst: &mut dyn Write,
+    header: &mut Header,
+    mut pax: PaxBuilder,
+    blocks: &[(u64, u64)],
+    size: u64,
+    format: ArchiveFormat,
+    data: &mut dyn Read,
+) -> io::Result<()> {
+    let end = blocks.last().map_or(0, |&(off, len)| off + len);
+    let stored: u64 = blocks.iter().map(|&(_, len)| len).sum();
+
+    match format {
+        ArchiveFormat::Gnu => {
+            let mut entries = blocks
+                .iter()
+                .map(|&(offset, num_bytes)| SparseEntry { offset, num_bytes })
+                .collect::<Vec<_>>();
+            // The map describes the whole file, so a hole at the end needs an
+            // empty block.
+            if end < size || entries.is_empty() {
+                entries.push(SparseEntry {
+                    offset: size,
+                    num_bytes: 0,
+                });
+            }
+            let entries = SparseEntries {
+                entries,
+                on_disk_size: stored,
+            };
+            set_header_sparse(header, &entries);
+            append_pax(dst, &pax)?;
+            header.set_cksum();
+            dst.write_all(header.as_bytes())?;
+            append_extended_sparse_headers(dst, &entries)?;
+            copy_exact(data, dst, stored)
+        }
+        ArchiveFormat::Pax => {
+            pax.add(PAX_GNUSPARSEMAJOR, b"1");
+            pax.add(PAX_GNUSPARSEMINOR, b"0");
+            pax.add(PAX_GNUSPARSEREALSIZE, size.to_string().as_bytes());
+
+            // The map comes first in the data of the entry, padded to a whole
+            // block. Like the GNU map it ends with an empty block at the end
+            // of the file if there's a hole there, as GNU tar otherwise
+            // truncates the file at the end of its last block of data.
+            let mut map_blocks = blocks.to_vec();
+            if end < size {
+                map_blocks.push((size, 0));
+            }
+            let mut map = format!("{}\n", map_blocks.len());
+            for &(off, len) in &map_blocks {
+                map.push_str(&format!("{}\n{}\n", off, len));
+            }
+            let map_len = (map.len() as u64 + BLOCK_SIZE - 1) & !(BLOCK_SIZE - 1);
+            header.set_entry_type(EntryType::Regular);
+            header.set_size(map_len + stored);
+            prepare_pax_numbers(header, format, &mut pax)?;
+            append_pax(dst, &pax)?;
+            header.set_cksum();
+            dst.write_all(header.as_bytes())?;
+            dst.write_all(map.as_bytes())?;
+            pad_zeroes(dst, map.len() as u64)?;
+            copy_exact(data, dst, stored)
+        }
+    }
+}
+
+/// Copies exactly `len` bytes from `data`, followed by padding to a whole
+/// block.
+fn copy_exact(data: &mut dyn Read, dst: &mut dyn Write, len: u64) -> io::Result<()> {
+    let copied = io::copy(&mut data.take(len), dst)?;
+    if copied < len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "unexpected EOF while copying entry data",
+        ));
+    }
+    pad_zeroes(dst, len)
+}
+
 #[cfg(unix)]
 fn append_special(
     dst: &mut dyn Write,
     path: &Path,
+    src: &Path,
     stat: &fs::Metadata,
-    mode: HeaderMode,
-    allow_absolute: bool,
+    options: &BuilderOptions,
+    links: &mut HardLinks,
 ) -> io::Result<()> {
     use ::std::os::unix::fs::{FileTypeExt, MetadataExt};
 
+    if let Some(target) = hard_link_target(path, stat, options, links) {
+        return append_hard_link(dst, path, stat, &target, options);
+    }
+
     let file_type = stat.file_type();
     let entry_type;
     if file_type.is_socket() {
@@ -693,9 +1539,14 @@ fn append_special(
         return Err(other(&format!("{} has unknown file type", path.display())));
     }
 
-    let mut header = Header::new_gnu();
-    header.set_metadata_in_mode(stat, mode);
-    prepare_header_path(dst, &mut header, path, allow_absolute)?;
+    let mut header = options.new_header();
+    header.set_metadata_in_mode(stat, options.mode);
+    prepare_header_ids(&mut header, path, options)?;
+    let mut pax = PaxBuilder::new();
+    prepare_header_path(dst, &mut header, path, options, &mut pax)?;
+    prepare_pax_times(stat, options, &mut pax);
+    prepare_pax_xattrs(src, None, options, &mut pax)?;
+    prepare_pax_acls(src, None, stat, options, &mut pax)?;
 
     header.set_entry_type(entry_type);
     let dev_id = stat.rdev();
@@ -704,6 +1555,7 @@ fn append_special(
     header.set_device_major(dev_major as u32)?;
     header.set_device_minor(dev_minor as u32)?;
 
+    append_pax(dst, &pax)?;
     header.set_cksum();
     dst.write_all(header.as_bytes())?;
 
@@ -714,18 +1566,61 @@ fn append_file(
     dst: &mut dyn Write,
     path: &Path,
     file: &mut fs::File,
-    options: BuilderOptions,
+    options: &BuilderOptions,
+    links: &mut HardLinks,
 ) -> io::Result<()> {
     let stat = file.metadata()?;
-    let mut header = Header::new_gnu();
-
-    prepare_header_path(dst, &mut header, path, options.preserve_absolute)?;
-    header.set_metadata_in_mode(&stat, options.mode);
+    if let Some(target) = hard_link_target(path, &stat, options, links) {
+        return append_hard_link(dst, path, &stat, &target, options);
+    }
+    let mut header = options.new_header();
     let sparse_entries = if options.sparse {
-        prepare_header_sparse(file, &stat, &mut header)?
+        find_sparse_entries(file, &stat)?
     } else {
         None
     };
+
+    let mut pax = PaxBuilder::new();
+    if sparse_entries.is_some() {
+        prepare_sparse_path(dst, &mut header, path, options, &mut pax, options.format)?;
+    } else {
+        prepare_header_path(dst, &mut header, path, options, &mut pax)?;
+    }
+    header.set_metadata_in_mode(&stat, options.mode);
+    prepare_header_ids(&mut header, path, options)?;
+    prepare_pax_times(&stat, options, &mut pax);
+    prepare_pax_xattrs(path, Some(file), options, &mut pax)?;
+    prepare_pax_acls(path, Some(file), &stat, options, &mut pax)?;
+
+    // Pax archives use the pax 1.0 sparse format, which stores the map of the
+    // file ahead of its data.
+    if let (Some(entries), ArchiveFormat::Pax) = (&sparse_entries, options.format) {
+        let blocks = entries
+            .entries
+            .iter()
+            .filter(|e| e.num_bytes != 0)
+            .map(|e| (e.offset, e.num_bytes))
+            .collect::<Vec<_>>();
+        let mut data = Regions {
+            obj: &mut *file,
+            regions: blocks.iter(),
+            remaining: 0,
+        };
+        return append_sparse(
+            dst,
+            &mut header,
+            pax,
+            &blocks,
+            entries.size(),
+            ArchiveFormat::Pax,
+            &mut data,
+        );
+    }
+    if let Some(entries) = &sparse_entries {
+        set_header_sparse(&mut header, entries);
+    }
+    prepare_pax_numbers(&mut header, options.format, &mut pax)?;
+    append_pax(dst, &pax)?;
     header.set_cksum();
     dst.write_all(header.as_bytes())?;
 
@@ -744,21 +1639,71 @@ fn append_file(
     Ok(())
 }
 
+fn append_hard_link(
+    dst: &mut dyn Write,
+    path: &Path,
+    stat: &fs::Metadata,
+    target: &Path,
+    options: &BuilderOptions,
+) -> io::Result<()> {
+    let mut header = options.new_header();
+
+    let mut pax = PaxBuilder::new();
+    prepare_header_path(dst, &mut header, path, options, &mut pax)?;
+    header.set_metadata_in_mode(stat, options.mode);
+    prepare_header_ids(&mut header, path, options)?;
+    prepare_pax_times(stat, options, &mut pax);
+    header.set_entry_type(EntryType::Link);
+    header.set_size(0);
+    prepare_header_link(dst, &mut header, target, options, &mut pax)?;
+    prepare_pax_numbers(&mut header, options.format, &mut pax)?;
+    append_pax(dst, &pax)?;
+    header.set_cksum();
+    dst.write_all(header.as_bytes())
+}
+
+/// Returns the path a file was previously archived under if `stat` is another
+/// hard link to it, or otherwise records `path` for later links to the file.
+#[cfg(unix)]
+fn hard_link_target(
+    path: &Path,
+    stat: &fs::Metadata,
+    options: &BuilderOptions,
+    links: &mut HardLinks,
+) -> Option<PathBuf> {
+    use std::collections::hash_map::Entry;
+    use std::os::unix::fs::MetadataExt;
+
+    if !options.hard_links || stat.nlink() <= 1 {
+        return None;
+    }
+    match links.entry((stat.dev(), stat.ino())) {
+        Entry::Occupied(e) => Some(e.get().clone()),
+        Entry::Vacant(e) => {
+            e.insert(path.to_path_buf());
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn hard_link_target(
+    _path: &Path,
+    _stat: &fs::Metadata,
+    _options: &BuilderOptions,
+    _links: &mut HardLinks,
+) -> Option<PathBuf> {
+    None
+}
+
 fn append_dir(
     dst: &mut dyn Write,
     path: &Path,
     src_path: &Path,
-    options: BuilderOptions,
+    options: &BuilderOptions,
 ) -> io::Result<()> {
     let stat = fs::metadata(src_path)?;
-    append_fs(
-        dst,
-        path,
-        &stat,
-        options.mode,
-        options.preserve_absolute,
-        None,
-    )
+    append_fs(dst, path, src_path, &stat, options, None)
 }
 
 fn prepare_header(size: u64, entry_type: u8) -> Header {
@@ -780,12 +1725,14 @@ fn prepare_header_path(
     dst: &mut dyn Write,
     header: &mut Header,
     path: &Path,
-    allow_absolute: bool,
+    options: &BuilderOptions,
+    pax: &mut PaxBuilder,
 ) -> io::Result<()> {
     // Try to encode the path directly in the header, but if it ends up not
     // working (probably because it's too long) then try to use the GNU-specific
     // long name extension by emitting an entry which indicates that it's the
-    // filename.
+    // filename, or a pax `path` record for pax archives.
+    let allow_absolute = options.preserve_absolute;
     let result = if allow_absolute {
         header.set_path_absolute(path)
     } else {
@@ -813,12 +1760,13 @@ fn prepare_header_path(
             Ok(s) => s,
             Err(e) => str::from_utf8(&data[..e.valid_up_to()]).unwrap(),
         };
-        header.set_truncated_path_for_gnu_header(truncated, allow_absolute)?;
+        header.set_truncated_path(truncated, allow_absolute)?;
 
-        let header2 = prepare_header(data.len() as u64, b'L');
-        // null-terminated string
-        let mut data2 = data.chain(io::repeat(0).take(1));
-        append(dst, &header2, &mut data2)?;
+        if options.format == ArchiveFormat::Pax {
+            pax.add(PAX_PATH, &data);
+            return Ok(());
+        }
+        append_long_name(dst, b'L', &data)?;
     }
     Ok(())
 }
@@ -827,6 +1775,8 @@ fn prepare_header_link(
     dst: &mut dyn Write,
     header: &mut Header,
     link_name: &Path,
+    options: &BuilderOptions,
+    pax: &mut PaxBuilder,
 ) -> io::Result<()> {
     // Same as previous function but for linkname
     if let Err(e) = header.set_link_name(link_name) {
@@ -834,23 +1784,258 @@ fn prepare_header_link(
         if data.len() < header.as_old().linkname.len() {
             return Err(e);
         }
-        let header2 = prepare_header(data.len() as u64, b'K');
-        let mut data2 = data.chain(io::repeat(0).take(1));
-        append(dst, &header2, &mut data2)?;
+        if options.format == ArchiveFormat::Pax {
+            pax.add(PAX_LINKPATH, &data);
+            return Ok(());
+        }
+        append_long_name(dst, b'K', &data)?;
     }
     Ok(())
 }
 
-fn prepare_header_sparse(
-    file: &mut fs::File,
-    stat: &fs::Metadata,
+fn prepare_header_ids(
     header: &mut Header,
-) -> io::Result<Option<SparseEntries>> {
-    let entries = match find_sparse_entries(file, stat)? {
-        Some(entries) => entries,
-        _ => return Ok(None),
+    path: &Path,
+    options: &BuilderOptions,
+) -> io::Result<()> {
+    if options.mode != HeaderMode::Complete {
+        return Ok(());
+    }
+    if let Some(map) = &options.uid_map {
+        let uid = header.uid()?;
+        match map.to_inside(uid) {
+            Some(uid) => header.set_uid(uid),
+            None => {
+                return Err(other(&format!(
+                    "uid {} of {} is not mapped",
+                    uid,
+                    path.display()
+                )))
+            }
+        }
+    }
+    if let Some(map) = &options.gid_map {
+        let gid = header.gid()?;
+        match map.to_inside(gid) {
+            Some(gid) => header.set_gid(gid),
+            None => {
+                return Err(other(&format!(
+                    "gid {} of {} is not mapped",
+                    gid,
+                    path.display()
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+fn prepare_pax_times(meta: &fs::Metadata, options: &BuilderOptions, pax: &mut PaxBuilder) {
+    if !options.pax_timestamps || options.mode != HeaderMode::Complete {
+        return;
+    }
+    if let Ok(mtime) = meta.modified() {
+        pax.add(PAX_MTIME, format_pax_time(mtime).as_bytes());
+    }
+    if let Ok(atime) = meta.accessed() {
+        pax.add(PAX_ATIME, format_pax_time(atime).as_bytes());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let secs = Duration::from_secs(meta.ctime().unsigned_abs());
+        let ctime = if meta.ctime() < 0 {
+            UNIX_EPOCH.checked_sub(secs)
+        } else {
+            UNIX_EPOCH.checked_add(secs)
+        };
+        let nanos = Duration::from_nanos(meta.ctime_nsec() as u64);
+        if let Some(ctime) = ctime.and_then(|t| t.checked_add(nanos)) {
+            pax.add(PAX_CTIME, format_pax_time(ctime).as_bytes());
+        }
+    }
+}
+
+/// Moves the uid, gid, size and mtime of `header` to pax records when writing
+/// a pax archive and they're too large for the octal fields of the header.
+///
+/// The header gets zero for them instead of the GNU base-256 encoding of the
+/// values, which strict ustar readers reject.
+fn prepare_pax_numbers(
+    header: &mut Header,
+    format: ArchiveFormat,
+    pax: &mut PaxBuilder,
+) -> io::Result<()> {
+    if format != ArchiveFormat::Pax {
+        return Ok(());
+    }
+    // Only values which don't fit in octal are written in base-256, marked
+    // by the high bit of the first byte of the field.
+    let old = header.as_old();
+    let base256 = |field: &[u8]| field[0] & 0x80 != 0;
+    let (uid, gid, size, mtime) = (
+        base256(&old.uid),
+        base256(&old.gid),
+        base256(&old.size),
+        base256(&old.mtime),
+    );
+    if uid {
+        pax.add(PAX_UID, header.uid()?.to_string().as_bytes());
+        header.set_uid(0);
+    }
+    if gid {
+        pax.add(PAX_GID, header.gid()?.to_string().as_bytes());
+        header.set_gid(0);
+    }
+    if size {
+        pax.add(PAX_SIZE, header.size()?.to_string().as_bytes());
+        header.set_size(0);
+    }
+    if mtime {
+        if !pax.contains(PAX_MTIME) {
+            pax.add(PAX_MTIME, header.mtime()?.to_string().as_bytes());
+        }
+        header.set_mtime(0);
+    }
+    Ok(())
+}
+
+/// Adds the extended attributes of `src` which are captured as
+/// `SCHILY.xattr.*` records, reading them from `file` instead if it's the
+/// already opened file.
+#[cfg(all(unix, not(target_arch = "wasm32"), feature = "xattr"))]
+fn prepare_pax_xattrs(
+    src: &Path,
+    file: Option<&fs::File>,
+    options: &BuilderOptions,
+    pax: &mut PaxBuilder,
+) -> io::Result<()> {
+    use crate::pax::PAX_SCHILYXATTR;
+    use xattr::FileExt;
+
+    let filter = match &options.xattrs {
+        Some(filter) => filter,
+        None => return Ok(()),
+    };
+    // Symlinks which aren't followed have attributes of their own.
+    let names = match file {
+        Some(file) => file.list_xattr(),
+        None if options.follow => xattr::list_deref(src),
+        None => xattr::list(src),
     };
+    let names = match names {
+        Ok(names) => names,
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for name in names {
+        // Pax record keys are UTF-8, so other names can't be stored.
+        let key = match name.to_str() {
+            Some(key) => key,
+            None => continue,
+        };
+        // ACLs are captured as `SCHILY.acl.*` records by `prepare_pax_acls`.
+        if key.starts_with("system.posix_acl_") || !filter.matches(key) {
+            continue;
+        }
+        let value = match file {
+            Some(file) => file.get_xattr(&name),
+            None if options.follow => xattr::get_deref(src, &name),
+            None => xattr::get(src, &name),
+        };
+        // Attributes removed since being listed are skipped.
+        if let Some(value) = value? {
+            pax.add(&format!("{}{}", PAX_SCHILYXATTR, key), &value);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(all(unix, not(target_arch = "wasm32"), feature = "xattr")))]
+fn prepare_pax_xattrs(
+    _src: &Path,
+    _file: Option<&fs::File>,
+    _options: &BuilderOptions,
+    _pax: &mut PaxBuilder,
+) -> io::Result<()> {
+    Ok(())
+}
+
+/// Adds the access ACL of `src`, and the default ACL if it's a directory, as
+/// `SCHILY.acl.*` records, reading them from `file` instead if it's the
+/// already opened file.
+#[cfg(all(target_os = "linux", feature = "acl"))]
+fn prepare_pax_acls(
+    src: &Path,
+    file: Option<&fs::File>,
+    meta: &fs::Metadata,
+    options: &BuilderOptions,
+    pax: &mut PaxBuilder,
+) -> io::Result<()> {
+    use crate::pax::{PAX_SCHILYACLACCESS, PAX_SCHILYACLDEFAULT};
+    use crate::{Acl, AclTag};
+    use xattr::FileExt;
 
+    // Symlinks themselves have no ACLs.
+    if !options.acls || meta.file_type().is_symlink() {
+        return Ok(());
+    }
+    let mut acls = vec![(PAX_SCHILYACLACCESS, "system.posix_acl_access")];
+    if meta.is_dir() {
+        acls.push((PAX_SCHILYACLDEFAULT, "system.posix_acl_default"));
+    }
+    for (key, name) in acls {
+        let value = match file {
+            Some(file) => file.get_xattr(name),
+            None if options.follow => xattr::get_deref(src, name),
+            None => xattr::get(src, name),
+        };
+        // Files without extended ACLs, or on filesystems without support for
+        // them, have none to capture.
+        let value = match value {
+            Ok(Some(value)) => value,
+            Ok(None) => continue,
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let acl = Acl::from_xattr(&value, |tag, id| {
+            let (kind, map) = match tag {
+                AclTag::User => ("uid", &options.uid_map),
+                _ => ("gid", &options.gid_map),
+            };
+            match map {
+                Some(map) => map.to_inside(id).ok_or_else(|| {
+                    other(&format!(
+                        "{} {} in the ACL of {} is not mapped",
+                        kind,
+                        id,
+                        src.display()
+                    ))
+                }),
+                None => Ok(id),
+            }
+        })?;
+        pax.add(key, acl.to_string().as_bytes());
+    }
+    Ok(())
+}
+
+#[cfg(not(all(target_os = "linux", feature = "acl")))]
+fn prepare_pax_acls(
+    _src: &Path,
+    _file: Option<&fs::File>,
+    _meta: &fs::Metadata,
+    _options: &BuilderOptions,
+    _pax: &mut PaxBuilder,
+) -> io::Result<()> {
+    Ok(())
+}
+
+/// Describes the sparse file `entries` in `header`, which must be a GNU
+/// header.
+fn set_header_sparse(header: &mut Header, entries: &SparseEntries) {
     header.set_entry_type(EntryType::GNUSparse);
     header.set_size(entries.on_disk_size);
 
@@ -861,13 +2046,15 @@ fn prepare_header_sparse(
     let gnu_header = &mut header.as_gnu_mut().unwrap();
     gnu_header.set_real_size(entries.size());
 
+    for header_entry in gnu_header.sparse.iter_mut() {
+        header_entry.offset = [0; 12];
+        header_entry.numbytes = [0; 12];
+    }
     for (entry, header_entry) in std::iter::zip(&entries.entries, &mut gnu_header.sparse) {
         header_entry.set_offset(entry.offset);
         header_entry.set_length(entry.num_bytes);
     }
     gnu_header.set_is_extended(entries.entries.len() > gnu_header.sparse.len());
-
-    Ok(Some(entries))
 }
 
 /// Write extra sparse headers into `dst` for those entries that did not fit in the main header.
@@ -901,18 +2088,25 @@ fn append_extended_sparse_headers(dst: &mut dyn Write, entries: &SparseEntries)
 fn append_fs(
     dst: &mut dyn Write,
     path: &Path,
+    src: &Path,
     meta: &fs::Metadata,
-    mode: HeaderMode,
-    allow_absolute: bool,
+    options: &BuilderOptions,
     link_name: Option<&Path>,
 ) -> io::Result<()> {
-    let mut header = Header::new_gnu();
-
-    prepare_header_path(dst, &mut header, path, allow_absolute)?;
-    header.set_metadata_in_mode(meta, mode);
+    let mut header = options.new_header();
+
+    let mut pax = PaxBuilder::new();
+    prepare_header_path(dst, &mut header, path, options, &mut pax)?;
+    header.set_metadata_in_mode(meta, options.mode);
+    prepare_header_ids(&mut header, path, options)?;
+    prepare_pax_times(meta, options, &mut pax);
+    prepare_pax_xattrs(src, None, options, &mut pax)?;
+    prepare_pax_acls(src, None, meta, options, &mut pax)?;
     if let Some(link_name) = link_name {
-        prepare_header_link(dst, &mut header, link_name)?;
+        prepare_header_link(dst, &mut header, link_name, options, &mut pax)?;
     }
+    prepare_pax_numbers(&mut header, options.format, &mut pax)?;
+    append_pax(dst, &pax)?;
     header.set_cksum();
     dst.write_all(header.as_bytes())
 }
@@ -921,17 +2115,31 @@ fn append_dir_all(
     dst: &mut dyn Write,
     path: &Path,
     src_path: &Path,
-    options: BuilderOptions,
+    options: &BuilderOptions,
+    links: &mut HardLinks,
+    filter: &mut dyn FnMut(&Path, &fs::Metadata) -> bool,
 ) -> io::Result<()> {
     let mut stack = vec![(src_path.to_path_buf(), true, false)];
     while let Some((src, is_dir, is_symlink)) = stack.pop() {
         let dest = path.join(src.strip_prefix(src_path).unwrap());
         // In case of a symlink pointing to a directory, is_dir is false, but src.is_dir() will return true
         if is_dir || (is_symlink && options.follow && src.is_dir()) {
-            for entry in fs::read_dir(&src)? {
-                let entry = entry?;
+            let mut entries = fs::read_dir(&src)?.collect::<io::Result<Vec<_>>>()?;
+            if options.sort {
+                // Entries are popped off the stack, so push them in reverse.
+                entries.sort_by_key(|entry| entry.file_name());
+                entries.reverse();
+            }
+            for entry in entries {
                 let file_type = entry.file_type()?;
-                stack.push((entry.path(), file_type.is_dir(), file_type.is_symlink()));
+                let entry_path = entry.path();
+                // Symlinks are only followed for what the filter selects, so
+                // that it can skip dangling ones.
+                let relative = entry_path.strip_prefix(src_path).unwrap();
+                if !filter(relative, &entry.metadata()?) {
+                    continue;
+                }
+                stack.push((entry_path, file_type.is_dir(), file_type.is_symlink()));
             }
             if dest != Path::new("") {
                 append_dir(dst, &dest, &src, options)?;
@@ -939,24 +2147,17 @@ fn append_dir_all(
         } else if !options.follow && is_symlink {
             let stat = fs::symlink_metadata(&src)?;
             let link_name = fs::read_link(&src)?;
-            append_fs(
-                dst,
-                &dest,
-                &stat,
-                options.mode,
-                options.preserve_absolute,
-                Some(&link_name),
-            )?;
+            append_fs(dst, &dest, &src, &stat, options, Some(&link_name))?;
         } else {
             #[cfg(unix)]
             {
                 let stat = fs::metadata(&src)?;
                 if !stat.is_file() {
-                    append_special(dst, &dest, &stat, options.mode, options.preserve_absolute)?;
+                    append_special(dst, &dest, &src, &stat, options, links)?;
                     continue;
                 }
             }
-            append_file(dst, &dest, &mut fs::File::open(src)?, options)?;
+            append_file(dst, &dest, &mut fs::File::open(src)?, options, links)?;
         }
     }
     Ok(())
@@ -1010,7 +2211,7 @@ fn find_sparse_entries_seek(
     use std::os::unix::fs::MetadataExt as _;
     use std::os::unix::io::AsRawFd as _;
 
-    fn lseek(file: &fs::File, offset: i64, whence: libc::c_int) -> Result<i64, i32> {
+    fn lseek(file: &fs::File, offset: i64, whence: std::os::raw::c_int) -> Result<i64, i32> {
         #[cfg(any(target_os = "linux", target_os = "android"))]
         let lseek = libc::lseek64;
         #[cfg(not(any(target_os = "linux", target_os = "android")))]
@@ -0,0 +1,995 @@
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::str;
+use std::time::UNIX_EPOCH;
+
+use crate::entry::{entry_link_name_bytes, entry_path_bytes};
+use crate::header::{bytes2path, BLOCK_SIZE};
+use crate::other;
+use crate::pax::*;
+use crate::{GnuExtSparseHeader, GnuSparseHeader, Header, PaxExtensions};
+
+/// A decoder for archives which does no I/O of its own.
+///
+/// Bytes of the archive are pushed into the decoder with `decode`, in chunks
+/// of any size, and it reports what it found in them as a series of
+/// [`Decoded`] events: the headers of each entry, followed by the entry's
+/// contents and finally the end of the archive. Long names, pax extensions
+/// and sparse files are interpreted exactly as [`Archive`](crate::Archive)
+/// does, which is itself built on this decoder.
+///
+/// Pax global extended headers are yielded as entries of their own, and once
+/// their contents have been decoded their records apply to all entries after
+/// them.
+///
+/// # Examples
+///
+/// ```
+/// use tar::{Builder, Decoded, Decoder, Header};
+///
+/// let mut header = Header::new_gnu();
+/// header.set_size(4);
+/// let mut ar = Builder::new(Vec::new());
+/// ar.append_data(&mut header, "foo.txt", &b"data"[..]).unwrap();
+/// let archive = ar.into_inner().unwrap();
+///
+/// let mut decoder = Decoder::new();
+/// // Bytes may arrive in chunks of any size, here 100 at a time.
+/// let mut chunks = archive.chunks(100);
+/// let mut input: &[u8] = &[];
+/// loop {
+///     let decoded = if input.is_empty() {
+///         match chunks.next() {
+///             Some(chunk) => {
+///                 input = chunk;
+///                 continue;
+///             }
+///             None => Some(decoder.finish().unwrap()),
+///         }
+///     } else {
+///         let (n, decoded) = decoder.decode(input).unwrap();
+///         input = &input[n..];
+///         decoded
+///     };
+///     match decoded {
+///         Some(Decoded::Entry(entry)) => println!("{}", entry.path().unwrap().display()),
+///         Some(Decoded::Data(data)) => println!("{} bytes of data", data.len()),
+///         Some(Decoded::Hole(len)) => println!("{} bytes of zeros", len),
+///         Some(Decoded::End) => break,
+///         None => {}
+///     }
+/// }
+/// ```
+pub struct Decoder {
+    raw: bool,
+    ignore_zeros: bool,
+    pos: u64,
+    next: u64,
+    state: State,
+    buf: Vec<u8>,
+    long_pathname: Option<Vec<u8>>,
+    long_linkname: Option<Vec<u8>>,
+    pax_extensions: Option<Vec<u8>>,
+    global_extensions: Option<Vec<u8>>,
+}
+
+/// An event produced by a [`Decoder`].
+pub enum Decoded<'a> {
+    /// All headers of the next entry have been read. Its contents follow as
+    /// `Data` and `Hole` events.
+    Entry(Box<DecodedEntry>),
+    /// Part of the contents of the current entry, borrowed from the input.
+    Data(&'a [u8]),
+    /// A run of zeros in the contents of the current entry, which is a sparse
+    /// file, of the given length. Holes aren't stored in the archive and so
+    /// consume no input.
+    Hole(u64),
+    /// The end of the archive has been reached.
+    End,
+}
+
+/// The headers of an entry, as decoded by a [`Decoder`].
+pub struct DecodedEntry {
+    pub(crate) header: Header,
+    /// The header as it was read, before pax records were applied to it.
+    pub(crate) raw_header: Header,
+    pub(crate) header_pos: u64,
+    pub(crate) file_pos: u64,
+    /// The size of the entry's contents, including any holes of sparse files.
+    pub(crate) size: u64,
+    pub(crate) long_pathname: Option<Vec<u8>>,
+    pub(crate) long_linkname: Option<Vec<u8>>,
+    pub(crate) pax_extensions: Option<Vec<u8>>,
+    /// The entry's own pax records together with the global records which
+    /// they don't override, or `None` if no global records apply.
+    pub(crate) effective_extensions: Option<Vec<u8>>,
+    pub(crate) global_extensions: Option<Vec<u8>>,
+    /// The `(offset, length)` blocks of a sparse file, whose data is stored
+    /// back to back in the archive starting at `file_pos`.
+    pub(crate) sparse: Option<Vec<(u64, u64)>>,
+}
+
+/// A run of an entry's contents, either stored in the archive or a hole of
+/// zeros in a sparse file.
+pub(crate) enum Chunk {
+    Data(u64),
+    Hole(u64),
+}
+
+enum State {
+    Header,
+    Extension {
+        kind: Extension,
+        remaining: u64,
+        data: Vec<u8>,
+    },
+    GnuSparse {
+        entry: DecodedEntry,
+        blocks: Vec<(u64, u64)>,
+    },
+    PaxSparseMap {
+        entry: DecodedEntry,
+        real_size: Option<u64>,
+        map: Vec<u8>,
+        parsed: usize,
+        numbers: Vec<u64>,
+    },
+    Data {
+        chunks: VecDeque<Chunk>,
+        padding: u64,
+        /// The contents of a pax global extended header read so far, whose
+        /// records are applied once all of them have been read.
+        global: Option<Vec<u8>>,
+    },
+    End,
+}
+
+#[derive(Clone, Copy)]
+enum Extension {
+    LongName,
+    LongLink,
+    Pax,
+}
+
+impl Decoder {
+    /// Creates a decoder for an archive starting at offset 0.
+    pub fn new() -> Decoder {
+        Decoder {
+            raw: false,
+            ignore_zeros: false,
+            pos: 0,
+            next: 0,
+            state: State::Header,
+            buf: Vec::new(),
+            long_pathname: None,
+            long_linkname: None,
+            pax_extensions: None,
+            global_extensions: None,
+        }
+    }
+
+    /// Indicates whether every header is yielded as its own entry, without
+    /// interpreting GNU long name, pax or sparse headers. Disabled by
+    /// default.
+    pub fn set_raw(&mut self, raw: bool) {
+        self.raw = raw;
+    }
+
+    /// Ignore zeroed headers, which would otherwise indicate the end of the
+    /// archive.
+    ///
+    /// This can be used in case multiple tar archives have been concatenated
+    /// together.
+    pub fn set_ignore_zeros(&mut self, ignore_zeros: bool) {
+        self.ignore_zeros = ignore_zeros;
+    }
+
+    /// Returns the records of the pax global extended headers seen so far,
+    /// which apply to all entries after them, if there are any.
+    ///
+    /// Records of later global headers replace those of earlier ones with the
+    /// same key, and records with empty values are removed.
+    pub fn pax_global_extensions(&self) -> Option<PaxExtensions<'_>> {
+        self.global_extensions.as_deref().map(PaxExtensions::new)
+    }
+
+    /// Returns the offset in the archive of the next byte the decoder
+    /// expects.
+    pub fn position(&self) -> u64 {
+        self.pos + self.buf.len() as u64
+    }
+
+    /// Consumes bytes of the archive from `input`, which continues where the
+    /// previous call left off.
+    ///
+    /// Returns the number of bytes of `input` consumed along with the next
+    /// event, if any. `None` is only returned once all of `input` has been
+    /// consumed, so the remaining input must be passed again after an event.
+    ///
+    /// Once the end of the archive has been reached this returns
+    /// `Decoded::End` without consuming any more input.
+    pub fn decode<'a>(&mut self, input: &'a [u8]) -> io::Result<(usize, Option<Decoded<'a>>)> {
+        let mut consumed = 0;
+        loop {
+            let input = &input[consumed..];
+            if let State::Data {
+                chunks,
+                padding,
+                global,
+            } = &mut self.state
+            {
+                match chunks.front_mut() {
+                    Some(Chunk::Hole(len)) => {
+                        let len = *len;
+                        chunks.pop_front();
+                        if len > 0 {
+                            return Ok((consumed, Some(Decoded::Hole(len))));
+                        }
+                    }
+                    Some(Chunk::Data(0)) => {
+                        chunks.pop_front();
+                    }
+                    Some(Chunk::Data(len)) => {
+                        if input.is_empty() {
+                            return Ok((consumed, None));
+                        }
+                        let n = cmp::min(*len, input.len() as u64);
+                        *len -= n;
+                        if *len == 0 {
+                            chunks.pop_front();
+                        }
+                        self.pos += n;
+                        let data = &input[..n as usize];
+                        if let Some(records) = global {
+                            records.extend_from_slice(data);
+                            if chunks.is_empty() {
+                                let records = mem::take(records);
+                                self.set_global_extensions(&records);
+                            }
+                        }
+                        return Ok((consumed + n as usize, Some(Decoded::Data(data))));
+                    }
+                    None if *padding == 0 => self.state = State::Header,
+                    None => {
+                        if input.is_empty() {
+                            return Ok((consumed, None));
+                        }
+                        let n = cmp::min(*padding, input.len() as u64);
+                        *padding -= n;
+                        self.pos += n;
+                        consumed += n as usize;
+                    }
+                }
+                continue;
+            }
+            if let State::End = self.state {
+                return Ok((consumed, Some(Decoded::End)));
+            }
+            if input.is_empty() {
+                return Ok((consumed, None));
+            }
+
+            // Headers are only decoded a whole block at a time, so buffer
+            // partial blocks until the rest arrives.
+            let decoded = if self.buf.is_empty() && input.len() >= BLOCK_SIZE as usize {
+                consumed += BLOCK_SIZE as usize;
+                self.push(Some(input[..BLOCK_SIZE as usize].try_into().unwrap()))?
+            } else {
+                let n = cmp::min(BLOCK_SIZE as usize - self.buf.len(), input.len());
+                self.buf.extend_from_slice(&input[..n]);
+                consumed += n;
+                if self.buf.len() < BLOCK_SIZE as usize {
+                    continue;
+                }
+                let buf = mem::take(&mut self.buf);
+                self.push(Some(buf[..].try_into().unwrap()))?
+            };
+            if let Some(decoded) = decoded {
+                return Ok((consumed, Some(decoded)));
+            }
+        }
+    }
+
+    /// Signals that the archive has no more bytes after those already
+    /// passed to `decode`.
+    ///
+    /// This returns any holes left at the end of the current entry, then
+    /// `Decoded::End` or an error if the archive was truncated.
+    pub fn finish(&mut self) -> io::Result<Decoded<'static>> {
+        loop {
+            if let (_, Some(decoded)) = self.decode(&[])? {
+                return Ok(decoded);
+            }
+            if let State::Data { .. } = self.state {
+                return Err(other("unexpected EOF in archive entry"));
+            }
+            if !self.buf.is_empty() {
+                return Err(other("failed to read entire block"));
+            }
+            if let Some(decoded) = self.push(None)? {
+                return Ok(decoded);
+            }
+        }
+    }
+
+    /// Skips the rest of the contents of the current entry, for callers which
+    /// read them, or seek past them, some other way.
+    ///
+    /// Afterwards `position` is the offset of the next header. Nothing
+    /// happens unless the last event was an entry or part of its contents.
+    ///
+    /// The records of a pax global extended header whose contents are
+    /// skipped aren't applied to the entries after it.
+    pub fn skip_data(&mut self) {
+        if let State::Data { .. } = self.state {
+            self.pos = self.next;
+            self.state = State::Header;
+        }
+    }
+
+    /// Consumes the block at `pos`, or `None` if the archive ended there.
+    ///
+    /// Returns `Ok(None)` when more blocks are needed.
+    fn push(
+        &mut self,
+        block: Option<&[u8; BLOCK_SIZE as usize]>,
+    ) -> io::Result<Option<Decoded<'static>>> {
+        match mem::replace(&mut self.state, State::Header) {
+            State::Header => self.header(block),
+            State::Extension {
+                kind,
+                remaining,
+                data,
+            } => self.extension(block, kind, remaining, data),
+            State::GnuSparse { entry, blocks } => self.gnu_sparse(block, entry, blocks),
+            State::PaxSparseMap {
+                entry,
+                real_size,
+                map,
+                parsed,
+                numbers,
+            } => self.pax_sparse_map(block, entry, real_size, map, parsed, numbers),
+            State::Data { .. } | State::End => unreachable!(),
+        }
+    }
+
+    fn header(
+        &mut self,
+        block: Option<&[u8; BLOCK_SIZE as usize]>,
+    ) -> io::Result<Option<Decoded<'static>>> {
+        // EOF is an indicator that we are at the end of the archive.
+        let block = match block {
+            Some(block) => block,
+            None => return self.end(),
+        };
+        let header_pos = self.pos;
+        self.pos += BLOCK_SIZE;
+
+        // If a header is not all zeros, we have another valid header.
+        // Otherwise, check if we are ignoring zeros and continue, or stop as if
+        // this is the end of the archive.
+        if block.iter().all(|i| *i == 0) {
+            if self.ignore_zeros {
+                self.next = self.pos;
+                return Ok(None);
+            }
+            return self.end();
+        }
+        let mut header = Header::new_old();
+        header.as_mut_bytes().copy_from_slice(block);
+
+        check_cksum(&header)?;
+
+        let is_recognized_header = header.as_gnu().is_some() || header.as_ustar().is_some();
+        let kind = if self.raw || !is_recognized_header {
+            None
+        } else if header.entry_type().is_gnu_longname() {
+            Some(Extension::LongName)
+        } else if header.entry_type().is_gnu_longlink() {
+            Some(Extension::LongLink)
+        } else if header.entry_type().is_pax_local_extensions() {
+            Some(Extension::Pax)
+        } else {
+            None
+        };
+        let is_global = !self.raw && header.entry_type().is_pax_global_extensions();
+
+        // Global records describe every entry which follows, unless the
+        // entry's own records override them.
+        let effective = match (kind, is_global, &self.global_extensions) {
+            (None, false, Some(global)) => Some(with_global_extensions(
+                global,
+                self.pax_extensions.as_deref(),
+            )),
+            _ => None,
+        };
+        let raw_header = header.clone();
+
+        let mut pax_size: Option<u64> = None;
+        let records = effective.as_deref().or(self.pax_extensions.as_deref());
+        if let (false, Some(pax_extensions_ref)) = (is_global, records) {
+            pax_size = pax_extensions_value(pax_extensions_ref, PAX_SIZE);
+
+            if let Some(pax_uid) = pax_extensions_value(pax_extensions_ref, PAX_UID) {
+                header.set_uid(pax_uid);
+            }
+
+            if let Some(pax_gid) = pax_extensions_value(pax_extensions_ref, PAX_GID) {
+                header.set_gid(pax_gid);
+            }
+
+            // Only whole seconds fit in the header, `Entry::mtime` provides
+            // the full precision.
+            if let Ok(Some(mtime)) = pax_extensions_time(pax_extensions_ref, PAX_MTIME) {
+                if let Ok(mtime) = mtime.duration_since(UNIX_EPOCH) {
+                    header.set_mtime(mtime.as_secs());
+                }
+            }
+        }
+
+        let file_pos = self.pos;
+        let mut size = header.entry_size()?;
+        // If this exists, it must override the header size. Disagreement among
+        // parsers allows construction of malicious archives that appear different
+        // when parsed.
+        if let Some(pax_size) = pax_size {
+            size = pax_size;
+        }
+
+        // Store where the next entry is, rounding up by 512 bytes (the size of
+        // a header);
+        let padded = size
+            .checked_add(BLOCK_SIZE - 1)
+            .ok_or_else(|| other("size overflow"))?;
+        self.next = file_pos
+            .checked_add(padded & !(BLOCK_SIZE - 1))
+            .ok_or_else(|| other("size overflow"))?;
+
+        let entry = DecodedEntry {
+            header,
+            raw_header,
+            header_pos,
+            file_pos,
+            size,
+            long_pathname: None,
+            long_linkname: None,
+            pax_extensions: None,
+            effective_extensions: None,
+            global_extensions: None,
+            sparse: None,
+        };
+        if self.raw {
+            return Ok(Some(self.emit(entry)));
+        }
+
+        // Global headers are yielded as entries too, collecting their
+        // contents as they're decoded. They don't consume the extensions
+        // read before them, which describe the next member.
+        if is_global {
+            let mut entry = entry;
+            entry.global_extensions = self.global_extensions.clone();
+            let decoded = self.emit(entry);
+            if let State::Data { global, .. } = &mut self.state {
+                *global = Some(Vec::new());
+            }
+            return Ok(Some(decoded));
+        }
+
+        if let Some(kind) = kind {
+            if self.extension_slot(kind).is_some() {
+                return Err(match kind {
+                    Extension::LongName | Extension::LongLink => other(
+                        "two long name entries describing \
+                         the same member",
+                    ),
+                    Extension::Pax => other(
+                        "two pax extensions entries describing \
+                         the same member",
+                    ),
+                });
+            }
+            if size == 0 {
+                self.finish_extension(kind, Vec::new());
+            } else {
+                self.state = State::Extension {
+                    kind,
+                    remaining: size,
+                    data: Vec::new(),
+                };
+            }
+            return Ok(None);
+        }
+
+        let mut entry = entry;
+        entry.long_pathname = self.long_pathname.take();
+        entry.long_linkname = self.long_linkname.take();
+        entry.pax_extensions = self.pax_extensions.take();
+        entry.effective_extensions = effective;
+        entry.global_extensions = self.global_extensions.clone();
+        self.parse_sparse_header(entry)
+    }
+
+    fn end(&mut self) -> io::Result<Option<Decoded<'static>>> {
+        if self.long_pathname.is_some()
+            || self.long_linkname.is_some()
+            || self.pax_extensions.is_some()
+        {
+            return Err(other(
+                "members found describing a future member \
+                 but no future member found",
+            ));
+        }
+        self.state = State::End;
+        Ok(Some(Decoded::End))
+    }
+
+    fn extension_slot(&mut self, kind: Extension) -> &mut Option<Vec<u8>> {
+        match kind {
+            Extension::LongName => &mut self.long_pathname,
+            Extension::LongLink => &mut self.long_linkname,
+            Extension::Pax => &mut self.pax_extensions,
+        }
+    }
+
+    /// Stores the contents of a long name or pax extension member.
+    fn finish_extension(&mut self, kind: Extension, data: Vec<u8>) {
+        *self.extension_slot(kind) = Some(data);
+    }
+
+    /// Returns whether the current entry is a pax global extended header
+    /// whose contents the decoder is waiting for.
+    pub(crate) fn reads_global_extensions(&self) -> bool {
+        matches!(
+            self.state,
+            State::Data {
+                global: Some(_),
+                ..
+            }
+        )
+    }
+
+    /// Merges the records of a pax global extended header with contents
+    /// `data` into those seen before.
+    pub(crate) fn set_global_extensions(&mut self, data: &[u8]) {
+        let mut records = Vec::new();
+        let old = self.global_extensions.take().unwrap_or_default();
+        for ext in PaxExtensions::new(&old).chain(PaxExtensions::new(data)) {
+            let (key, value) = match ext {
+                Ok(ext) => match ext.key() {
+                    Ok(key) => (key, ext.value_bytes()),
+                    Err(_) => continue,
+                },
+                Err(_) => continue,
+            };
+            records.retain(|&(k, _)| k != key);
+            if !value.is_empty() {
+                records.push((key, value));
+            }
+        }
+        let mut pax = PaxBuilder::new();
+        for (key, value) in records {
+            pax.add(key, value);
+        }
+        if !pax.is_empty() {
+            self.global_extensions = Some(pax.as_bytes().to_vec());
+        }
+    }
+
+    /// Collects the contents of a long name or pax extension member.
+    fn extension(
+        &mut self,
+        block: Option<&[u8; BLOCK_SIZE as usize]>,
+        kind: Extension,
+        mut remaining: u64,
+        mut data: Vec<u8>,
+    ) -> io::Result<Option<Decoded<'static>>> {
+        let block = block.ok_or_else(|| {
+            other(
+                "members found describing a future member \
+                 but no future member found",
+            )
+        })?;
+        let n = cmp::min(remaining, BLOCK_SIZE);
+        data.extend_from_slice(&block[..n as usize]);
+        remaining -= n;
+        self.pos += BLOCK_SIZE;
+        if remaining > 0 {
+            self.state = State::Extension {
+                kind,
+                remaining,
+                data,
+            };
+        } else {
+            self.finish_extension(kind, data);
+        }
+        Ok(None)
+    }
+
+    fn parse_sparse_header(&mut self, entry: DecodedEntry) -> io::Result<Option<Decoded<'static>>> {
+        if let Some(pax) = entry.pax_extensions.as_deref() {
+            if let Some(sparse) = pax_sparse(pax)? {
+                return match sparse.map {
+                    Some(blocks) => self.finish_pax_sparse(entry, blocks, sparse.real_size),
+                    // Format 1.0 stores the map at the start of the file
+                    // data, padded out to a whole block.
+                    None if entry.size < BLOCK_SIZE => {
+                        Err(other("pax sparse file map extends past end of entry"))
+                    }
+                    None => {
+                        self.state = State::PaxSparseMap {
+                            entry,
+                            real_size: sparse.real_size,
+                            map: Vec::new(),
+                            parsed: 0,
+                            numbers: Vec::new(),
+                        };
+                        Ok(None)
+                    }
+                };
+            }
+        }
+        if !entry.header.entry_type().is_gnu_sparse() {
+            return Ok(Some(self.emit(entry)));
+        }
+        let gnu = match entry.header.as_gnu() {
+            Some(gnu) => gnu,
+            None => return Err(other("sparse entry type listed but not GNU header")),
+        };
+
+        // Blocks of a sparse file are described by the `GnuSparseHeader`
+        // structure, some of which are contained in `GnuHeader` but some of
+        // which may also be contained after the first header in further
+        // headers.
+        //
+        // We read off all the blocks here and then hand them to
+        // `add_sparse_blocks`, which also validates that each block is
+        // aligned to a 512-byte boundary in the archive itself.
+        let mut blocks = Vec::new();
+        for block in gnu.sparse.iter() {
+            add_block(&mut blocks, block)?;
+        }
+        if gnu.is_extended() {
+            self.state = State::GnuSparse { entry, blocks };
+            return Ok(None);
+        }
+        self.finish_gnu_sparse(entry, blocks)
+    }
+
+    fn gnu_sparse(
+        &mut self,
+        block: Option<&[u8; BLOCK_SIZE as usize]>,
+        mut entry: DecodedEntry,
+        mut blocks: Vec<(u64, u64)>,
+    ) -> io::Result<Option<Decoded<'static>>> {
+        let block = block.ok_or_else(|| other("failed to read extension"))?;
+        let mut ext = GnuExtSparseHeader::new();
+        ext.as_mut_bytes().copy_from_slice(block);
+
+        // The data of the entry follows the extension headers.
+        self.pos += BLOCK_SIZE;
+        self.next += BLOCK_SIZE;
+        entry.file_pos += BLOCK_SIZE;
+        for block in ext.sparse.iter() {
+            add_block(&mut blocks, block)?;
+        }
+        if ext.is_extended() {
+            self.state = State::GnuSparse { entry, blocks };
+            return Ok(None);
+        }
+        self.finish_gnu_sparse(entry, blocks)
+    }
+
+    fn finish_gnu_sparse(
+        &mut self,
+        mut entry: DecodedEntry,
+        blocks: Vec<(u64, u64)>,
+    ) -> io::Result<Option<Decoded<'static>>> {
+        let real_size = entry.header.as_gnu().unwrap().real_size()?;
+        add_sparse_blocks(&mut entry, blocks, true)?;
+        if entry.size != real_size {
+            return Err(other(
+                "mismatch in sparse file chunks and \
+                 size in header",
+            ));
+        }
+        Ok(Some(self.emit(entry)))
+    }
+
+    /// Reads the decimal sparse map which precedes the data of a pax format
+    /// 1.0 sparse file, a line per number.
+    fn pax_sparse_map(
+        &mut self,
+        block: Option<&[u8; BLOCK_SIZE as usize]>,
+        mut entry: DecodedEntry,
+        real_size: Option<u64>,
+        mut map: Vec<u8>,
+        mut parsed: usize,
+        mut numbers: Vec<u64>,
+    ) -> io::Result<Option<Decoded<'static>>> {
+        let block = block.ok_or_else(|| other("failed to read pax sparse file map"))?;
+        map.extend_from_slice(block);
+        self.pos += BLOCK_SIZE;
+
+        while let Some(i) = map[parsed..].iter().position(|b| *b == b'\n') {
+            let num = str::from_utf8(&map[parsed..parsed + i])
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| other("malformed pax sparse file map"))?;
+            parsed += i + 1;
+            numbers.push(num);
+            let count = numbers[0];
+            if count.checked_mul(2).and_then(|n| n.checked_add(1)) == Some(numbers.len() as u64) {
+                let len = map.len() as u64;
+                entry.size -= len;
+                entry.file_pos += len;
+                let blocks = numbers[1..]
+                    .chunks(2)
+                    .map(|pair| (pair[0], pair[1]))
+                    .collect();
+                return self.finish_pax_sparse(entry, blocks, real_size);
+            }
+        }
+
+        if map.len() as u64 + BLOCK_SIZE > entry.size {
+            return Err(other("pax sparse file map extends past end of entry"));
+        }
+        self.state = State::PaxSparseMap {
+            entry,
+            real_size,
+            map,
+            parsed,
+            numbers,
+        };
+        Ok(None)
+    }
+
+    fn finish_pax_sparse(
+        &mut self,
+        mut entry: DecodedEntry,
+        blocks: Vec<(u64, u64)>,
+        real_size: Option<u64>,
+    ) -> io::Result<Option<Decoded<'static>>> {
+        add_sparse_blocks(&mut entry, blocks, false)?;
+        if let Some(real_size) = real_size {
+            if entry.size > real_size {
+                return Err(other(
+                    "mismatch in sparse file chunks and \
+                     size in header",
+                ));
+            }
+            // Unlike the GNU header format the map needn't describe a
+            // trailing hole.
+            entry.size = real_size;
+        }
+        Ok(Some(self.emit(entry)))
+    }
+
+    fn emit(&mut self, entry: DecodedEntry) -> Decoded<'static> {
+        let chunks: VecDeque<Chunk> = entry.chunks().into_iter().collect();
+        let stored: u64 = chunks
+            .iter()
+            .map(|chunk| match chunk {
+                Chunk::Data(len) => *len,
+                Chunk::Hole(_) => 0,
+            })
+            .sum();
+        self.state = State::Data {
+            chunks,
+            padding: self.next - self.pos - stored,
+            global: None,
+        };
+        Decoded::Entry(Box::new(entry))
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder::new()
+    }
+}
+
+impl DecodedEntry {
+    /// Returns access to the header of this entry in the archive.
+    ///
+    /// The uid, gid, mtime and size of the header are updated from any pax
+    /// extensions describing the entry.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Returns the path name for this entry.
+    ///
+    /// This behaves like [`Entry::path`](crate::Entry::path).
+    pub fn path(&self) -> io::Result<Cow<'_, Path>> {
+        bytes2path(self.path_bytes())
+    }
+
+    /// Returns the raw bytes listed for this entry.
+    ///
+    /// This behaves like [`Entry::path_bytes`](crate::Entry::path_bytes).
+    pub fn path_bytes(&self) -> Cow<'_, [u8]> {
+        entry_path_bytes(
+            &self.header,
+            self.long_pathname.as_deref(),
+            self.pax_extensions.as_deref(),
+        )
+    }
+
+    /// Returns the link name for this entry, if any is found.
+    ///
+    /// This behaves like [`Entry::link_name`](crate::Entry::link_name).
+    pub fn link_name(&self) -> io::Result<Option<Cow<'_, Path>>> {
+        match self.link_name_bytes() {
+            Some(bytes) => bytes2path(bytes).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the link name for this entry, in bytes, if listed.
+    ///
+    /// This behaves like [`Entry::link_name_bytes`](crate::Entry::link_name_bytes).
+    pub fn link_name_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        entry_link_name_bytes(
+            &self.header,
+            self.long_linkname.as_deref(),
+            self.pax_extensions.as_deref(),
+        )
+    }
+
+    /// Returns the pax extensions which preceded this entry, if any.
+    ///
+    /// Unlike [`Entry::pax_extensions`](crate::Entry::pax_extensions) the
+    /// records of a raw pax extensions entry are returned as its contents.
+    pub fn pax_extensions(&self) -> Option<PaxExtensions<'_>> {
+        self.pax_extensions.as_deref().map(PaxExtensions::new)
+    }
+
+    /// Returns the records of the pax global extended headers in effect for
+    /// this entry, if any.
+    ///
+    /// This behaves like
+    /// [`Entry::pax_global_extensions`](crate::Entry::pax_global_extensions).
+    pub fn pax_global_extensions(&self) -> Option<PaxExtensions<'_>> {
+        self.global_extensions.as_deref().map(PaxExtensions::new)
+    }
+
+    /// Returns the size of the contents of this entry, including the holes of
+    /// sparse files.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the starting position, in bytes, of the header of this entry in
+    /// the archive.
+    pub fn raw_header_position(&self) -> u64 {
+        self.header_pos
+    }
+
+    /// Returns the starting position, in bytes, of the contents of this entry
+    /// in the archive.
+    pub fn raw_file_position(&self) -> u64 {
+        self.file_pos
+    }
+
+    /// Returns the layout of the entry's contents, whose data is stored back
+    /// to back in the archive.
+    pub(crate) fn chunks(&self) -> Vec<Chunk> {
+        let blocks = match &self.sparse {
+            Some(blocks) => blocks,
+            None => return vec![Chunk::Data(self.size)],
+        };
+        let mut chunks = Vec::new();
+        let mut cur = 0;
+        for &(off, len) in blocks {
+            if cur < off {
+                chunks.push(Chunk::Hole(off - cur));
+            }
+            chunks.push(Chunk::Data(len));
+            cur = off + len;
+        }
+        if cur < self.size {
+            chunks.push(Chunk::Hole(self.size - cur));
+        }
+        chunks
+    }
+}
+
+/// Returns the records of `global` which `local` doesn't override, followed
+/// by those of `local`.
+///
+/// Global records which would change where an entry's contents are, or
+/// which file it is, are left out: they only make sense for a single member,
+/// and applying them to every member would let one header reframe the rest
+/// of the archive.
+fn with_global_extensions(global: &[u8], local: Option<&[u8]>) -> Vec<u8> {
+    let local = local.unwrap_or_default();
+    let mut pax = PaxBuilder::new();
+    for ext in PaxExtensions::new(global).filter_map(|e| e.ok()) {
+        let key = match ext.key() {
+            Ok(PAX_SIZE | PAX_PATH | PAX_LINKPATH) | Err(_) => continue,
+            Ok(key) if key.starts_with(PAX_GNUSPARSE) => continue,
+            Ok(key) => key,
+        };
+        let overridden = PaxExtensions::new(local)
+            .filter_map(|e| e.ok())
+            .any(|e| e.key_bytes() == ext.key_bytes());
+        if !overridden {
+            pax.add(key, ext.value_bytes());
+        }
+    }
+    let mut data = pax.as_bytes().to_vec();
+    data.extend_from_slice(local);
+    data
+}
+
+/// Makes sure the checksum of `header` is ok.
+pub(crate) fn check_cksum(header: &Header) -> io::Result<()> {
+    let sum = header.as_bytes()[..148]
+        .iter()
+        .chain(&header.as_bytes()[156..])
+        .fold(0, |a, b| a + (*b as u32))
+        + 8 * 32;
+    if sum != header.cksum()? {
+        return Err(other("archive header checksum mismatch"));
+    }
+    Ok(())
+}
+
+fn add_block(blocks: &mut Vec<(u64, u64)>, block: &GnuSparseHeader) -> io::Result<()> {
+    if !block.is_empty() {
+        blocks.push((block.offset()?, block.length()?));
+    }
+    Ok(())
+}
+
+/// Makes `entry` a sparse file of the given `(offset, length)` blocks,
+/// updating its size to the end of the last block.
+///
+/// This validates that each block comes after the previous and that the
+/// blocks consume exactly the data stored for the entry. For the old GNU
+/// format `aligned` additionally requires each block's data to start on a
+/// 512-byte boundary in the archive.
+fn add_sparse_blocks(
+    entry: &mut DecodedEntry,
+    blocks: Vec<(u64, u64)>,
+    aligned: bool,
+) -> io::Result<()> {
+    let size = entry.size;
+    let mut cur = 0;
+    let mut remaining = size;
+    for &(off, len) in &blocks {
+        if aligned && len != 0 && (size - remaining) % BLOCK_SIZE != 0 {
+            return Err(other(
+                "previous block in sparse file was not \
+                 aligned to 512-byte boundary",
+            ));
+        } else if off < cur {
+            return Err(other(
+                "out of order or overlapping sparse \
+                 blocks",
+            ));
+        }
+        cur = off
+            .checked_add(len)
+            .ok_or_else(|| other("more bytes listed in sparse file than u64 can hold"))?;
+        remaining = remaining.checked_sub(len).ok_or_else(|| {
+            other(
+                "sparse file consumed more data than the header \
+                 listed",
+            )
+        })?;
+    }
+    entry.size = cur;
+    if remaining > 0 {
+        return Err(other(
+            "mismatch in sparse file chunks and \
+             entry size in header",
+        ));
+    }
+    entry.sparse = Some(blocks);
+    Ok(())
+}
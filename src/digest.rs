@@ -0,0 +1,184 @@
+use std::borrow::Cow;
+use std::cmp;
+use std::io;
+use std::path::Path;
+
+use crate::decoder::{Decoded, Decoder};
+use crate::header::bytes2path;
+
+/// A hash function used to compute digests of entries and whole archives.
+///
+/// Digests are computed by [`Builder::digest`](crate::Builder::digest) for
+/// archives being written and [`Archive::set_digest`](crate::Archive::set_digest)
+/// for archives being read. A SHA-256 implementation is provided as [`Sha256`]
+/// with the `sha2` feature.
+pub trait Digest: Send + Sync {
+    /// Returns a new digest of the same kind, with no data hashed yet.
+    fn new_digest(&self) -> Box<dyn Digest>;
+
+    /// Hashes `data`, which follows all data hashed so far.
+    fn update(&mut self, data: &[u8]);
+
+    /// Returns the digest of all data hashed so far.
+    fn finish(&self) -> Vec<u8>;
+}
+
+/// The SHA-256 hash function.
+#[cfg(feature = "sha2")]
+#[derive(Clone, Default)]
+pub struct Sha256(sha2::Sha256);
+
+#[cfg(feature = "sha2")]
+impl Sha256 {
+    /// Creates a SHA-256 digest with no data hashed yet.
+    pub fn new() -> Sha256 {
+        Sha256::default()
+    }
+}
+
+#[cfg(feature = "sha2")]
+impl Digest for Sha256 {
+    fn new_digest(&self) -> Box<dyn Digest> {
+        Box::new(Sha256::new())
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(&mut self.0, data);
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        sha2::Digest::finalize(self.0.clone()).to_vec()
+    }
+}
+
+/// The digest of the contents of an entry written to an archive, as returned
+/// by [`Builder::take_entry_digests`](crate::Builder::take_entry_digests).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryDigest {
+    header_pos: u64,
+    path: Vec<u8>,
+    digest: Vec<u8>,
+}
+
+impl EntryDigest {
+    /// Returns the path of the entry.
+    ///
+    /// This behaves like [`Entry::path`](crate::Entry::path).
+    pub fn path(&self) -> io::Result<Cow<'_, Path>> {
+        bytes2path(Cow::Borrowed(&self.path))
+    }
+
+    /// Returns the raw bytes of the path of the entry.
+    pub fn path_bytes(&self) -> &[u8] {
+        &self.path
+    }
+
+    /// Returns the digest of the contents of the entry.
+    pub fn digest(&self) -> &[u8] {
+        &self.digest
+    }
+}
+
+/// Computes the digests of an archive and of the contents of each of its
+/// entries from the raw bytes of the archive.
+pub(crate) struct Digests {
+    archive: Box<dyn Digest>,
+    decoder: Decoder,
+    done: bool,
+    current: Option<(EntryDigest, u64, Box<dyn Digest>)>,
+    /// The most recently completed entry, when entries aren't collected.
+    last: Option<EntryDigest>,
+    /// The completed entries which haven't been taken yet, if they're
+    /// collected.
+    completed: Option<Vec<EntryDigest>>,
+}
+
+impl Digests {
+    /// Creates the digests of an archive, either collecting the digest of
+    /// every entry until they're taken or only keeping the last one.
+    pub(crate) fn new(digest: Box<dyn Digest>, collect: bool) -> Digests {
+        Digests {
+            archive: digest,
+            decoder: Decoder::new(),
+            done: false,
+            current: None,
+            last: None,
+            completed: if collect { Some(Vec::new()) } else { None },
+        }
+    }
+
+    pub(crate) fn set_ignore_zeros(&mut self, ignore_zeros: bool) {
+        self.decoder.set_ignore_zeros(ignore_zeros);
+    }
+
+    /// Hashes the next bytes of the archive.
+    pub(crate) fn update(&mut self, mut data: &[u8]) -> io::Result<()> {
+        self.archive.update(data);
+        while !self.done {
+            let (n, decoded) = self.decoder.decode(data)?;
+            data = &data[n..];
+            match decoded {
+                Some(Decoded::Entry(entry)) => {
+                    let current = EntryDigest {
+                        header_pos: entry.raw_header_position(),
+                        path: entry.path_bytes().into_owned(),
+                        digest: Vec::new(),
+                    };
+                    self.current = Some((current, entry.size(), self.archive.new_digest()));
+                }
+                Some(Decoded::Data(data)) => self.update_entry(data),
+                Some(Decoded::Hole(mut len)) => {
+                    let zeros = [0; 8192];
+                    while len > 0 {
+                        let n = cmp::min(len, zeros.len() as u64);
+                        self.update_entry(&zeros[..n as usize]);
+                        len -= n;
+                    }
+                }
+                Some(Decoded::End) => self.done = true,
+                None => break,
+            }
+            // Entries are complete as soon as all of their contents are seen,
+            // without waiting for the next header.
+            if let Some((_, 0, _)) = self.current {
+                let (mut entry, _, digest) = self.current.take().unwrap();
+                entry.digest = digest.finish();
+                match &mut self.completed {
+                    Some(completed) => completed.push(entry),
+                    None => self.last = Some(entry),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn update_entry(&mut self, data: &[u8]) {
+        if let Some((_, remaining, digest)) = &mut self.current {
+            digest.update(data);
+            *remaining -= data.len() as u64;
+        }
+    }
+
+    /// Returns the digest of all bytes of the archive seen so far.
+    pub(crate) fn archive_digest(&self) -> Vec<u8> {
+        self.archive.finish()
+    }
+
+    /// Returns the digests of the contents of the entries completed since
+    /// they were last taken, if they're collected.
+    pub(crate) fn take_entry_digests(&mut self) -> Vec<EntryDigest> {
+        self.completed
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Returns the digest of the contents of the entry whose header starts at
+    /// `header_pos`, if it's the last entry whose contents were all seen.
+    pub(crate) fn entry_digest(&self, header_pos: u64) -> Option<Vec<u8>> {
+        self.last
+            .as_ref()
+            .filter(|e| e.header_pos == header_pos)
+            .map(|e| e.digest.clone())
+    }
+}
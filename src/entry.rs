@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::cell::RefCell;
 use std::cmp;
 use std::fs;
 use std::fs::OpenOptions;
@@ -6,14 +7,22 @@ use std::io::prelude::*;
 use std::io::{self, Error, ErrorKind, SeekFrom};
 use std::marker;
 use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use filetime::{self, FileTime};
 
 use crate::archive::ArchiveInner;
+use crate::digest::Digests;
 use crate::error::TarError;
 use crate::header::bytes2path;
 use crate::other;
-use crate::{Archive, Header, PaxExtensions};
+use crate::pax::{
+    pax_extensions_time, PAX_ATIME, PAX_CTIME, PAX_GNAME, PAX_GNUSPARSENAME, PAX_MTIME,
+    PAX_SCHILYACLACCESS, PAX_SCHILYACLDEFAULT, PAX_UNAME,
+};
+use crate::{Acl, Archive, Header, IdLookup, IdMap, OwnershipPolicy, PaxExtensions};
+#[cfg(all(target_os = "linux", feature = "acl"))]
+use crate::{AclEntry, AclTag};
 
 /// A read-only view into an entry of an archive.
 ///
@@ -31,22 +40,81 @@ pub struct EntryFields<'a> {
     pub long_pathname: Option<Vec<u8>>,
     pub long_linkname: Option<Vec<u8>>,
     pub pax_extensions: Option<Vec<u8>>,
+    /// The entry's own pax records together with the global records which
+    /// they don't override, or `None` if no global records apply.
+    pub effective_extensions: Option<Vec<u8>>,
+    pub global_extensions: Option<Vec<u8>>,
     pub mask: u32,
     pub header: Header,
+    /// The header as it was read, before pax records were applied to it.
+    pub raw_header: Header,
     pub size: u64,
     pub header_pos: u64,
     pub file_pos: u64,
+    /// The `(offset, length)` blocks of a sparse file.
+    pub sparse: Option<Vec<(u64, u64)>>,
     pub data: Vec<EntryIo<'a>>,
     pub unpack_xattrs: bool,
+    pub unpack_acls: bool,
     pub preserve_permissions: bool,
     pub preserve_ownerships: bool,
     pub preserve_mtime: bool,
     pub overwrite: bool,
+    pub strip_components: usize,
+    /// Where the target of a hard link was unpacked to, relative to the
+    /// destination, when an `unpack_with` filter chose the paths of entries.
+    /// Otherwise the link name is used, less `strip_components`.
+    pub hard_link_target: Option<PathBuf>,
+    pub ownership_policy: OwnershipPolicy,
+    pub special_files: SpecialFilePolicy,
+    pub id_lookup: &'a (dyn IdLookup + Send),
+    pub uid_map: Option<&'a IdMap>,
+    pub gid_map: Option<&'a IdMap>,
+    pub digests: Option<&'a RefCell<Digests>>,
 }
 
 pub enum EntryIo<'a> {
     Pad(io::Take<io::Repeat>),
     Data(io::Take<&'a ArchiveInner<dyn Read + 'a>>),
+    /// Contents already read from the archive.
+    Buffered(io::Take<io::Cursor<Vec<u8>>>),
+}
+
+/// The regions of data of an entry, returned by [`Entry::sparse_regions`].
+///
+/// Regions are returned in order by [`SparseRegions::next_region`], and any data of
+/// the previous region which wasn't read is skipped.
+pub struct SparseRegions<'b, 'a> {
+    fields: &'b mut EntryFields<'a>,
+    next: usize,
+    remaining: u64,
+}
+
+/// A region of data of an entry, whose contents are read through its `Read`
+/// implementation.
+pub struct SparseRegion<'c, 'b, 'a> {
+    regions: &'c mut SparseRegions<'b, 'a>,
+    offset: u64,
+    len: u64,
+}
+
+/// How character devices, block devices and FIFOs are unpacked, as set with
+/// `Archive::set_special_file_policy`.
+///
+/// Creating devices usually needs elevated privileges, and none of these can
+/// be created on platforms other than Unix.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SpecialFilePolicy {
+    /// Create them with `mknod` and `mkfifo`, failing if that isn't
+    /// permitted or supported.
+    Create,
+    /// Create them, but skip those which can't be created because that isn't
+    /// permitted or supported. This is the default.
+    #[default]
+    CreateOrSkip,
+    /// Never create them.
+    Skip,
 }
 
 /// When unpacking items the unpacked thing is returned to allow custom
@@ -127,8 +195,10 @@ impl<'a, R: Read> Entry<'a, R> {
     /// contains extensions, or if there were no previous extensions describing
     /// it.
     ///
-    /// Note that global pax extensions are intended to be applied to all
-    /// archive entries.
+    /// Only the entry's own records are returned. Records of global pax
+    /// extended headers earlier in the archive still apply to the entry, and
+    /// are returned by `pax_global_extensions`. Global headers are yielded as
+    /// entries as well, for which this returns their own records.
     ///
     /// Also note that this function will read the entire entry if the entry
     /// itself is a list of extensions.
@@ -136,6 +206,21 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.pax_extensions()
     }
 
+    /// Returns the records of the pax global extended headers earlier in the
+    /// archive, which apply to this entry and all others after them.
+    ///
+    /// Records of later global headers replace those of earlier ones with the
+    /// same key. This can be used to read metadata about the whole archive,
+    /// such as the commit id `git archive` stores in a `comment` record.
+    ///
+    /// `None` is returned if no global headers preceded this entry.
+    pub fn pax_global_extensions(&self) -> Option<PaxExtensions<'_>> {
+        self.fields
+            .global_extensions
+            .as_deref()
+            .map(PaxExtensions::new)
+    }
+
     /// Returns access to the header of this entry in the archive.
     ///
     /// This provides access to the metadata for this entry in the archive.
@@ -143,6 +228,39 @@ impl<'a, R: Read> Entry<'a, R> {
         &self.fields.header
     }
 
+    /// Returns the modification time of this entry.
+    ///
+    /// If a pax `mtime` record describes this entry it is used, which may
+    /// carry sub-second precision and lie before the epoch. Otherwise the
+    /// whole seconds stored in the header are returned.
+    pub fn mtime(&self) -> io::Result<SystemTime> {
+        self.fields.mtime()
+    }
+
+    /// Returns the access time of this entry, if recorded in a pax `atime`
+    /// record.
+    pub fn atime(&self) -> io::Result<Option<SystemTime>> {
+        self.fields.pax_time(PAX_ATIME)
+    }
+
+    /// Returns the status change time of this entry, if recorded in a pax
+    /// `ctime` record.
+    pub fn ctime(&self) -> io::Result<Option<SystemTime>> {
+        self.fields.pax_time(PAX_CTIME)
+    }
+
+    /// Returns the access ACL of this entry, if recorded in a
+    /// `SCHILY.acl.access` record.
+    pub fn acl_access(&self) -> io::Result<Option<Acl>> {
+        self.fields.acl(PAX_SCHILYACLACCESS)
+    }
+
+    /// Returns the default ACL of this entry, which is inherited by files
+    /// created in a directory, if recorded in a `SCHILY.acl.default` record.
+    pub fn acl_default(&self) -> io::Result<Option<Acl>> {
+        self.fields.acl(PAX_SCHILYACLDEFAULT)
+    }
+
     /// Returns access to the size of this entry in the archive.
     ///
     /// In the event the size is stored in a pax extension, that size value
@@ -171,6 +289,73 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.file_pos
     }
 
+    /// Returns the `(offset, length)` regions of data of a sparse file, or
+    /// `None` if this entry isn't sparse.
+    ///
+    /// The regions are in order, and everything outside of them is a hole
+    /// which reads as zeros. Their data is stored back to back in the archive
+    /// from `raw_file_position`. The map may include empty regions, such as
+    /// the one at the end of a GNU sparse file which ends in a hole.
+    pub fn sparse_map(&self) -> Option<&[(u64, u64)]> {
+        self.fields.sparse.as_deref()
+    }
+
+    /// Returns the regions of data of this entry, to read each of them without
+    /// reading the holes of sparse files.
+    ///
+    /// There is a region for each block of `sparse_map`, or a single region
+    /// covering the whole contents of entries which aren't sparse. This must
+    /// be called before any of the contents of the entry are read, and the
+    /// entry itself shouldn't be read afterwards.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::io::Read;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// for entry in ar.entries().unwrap() {
+    ///     let mut entry = entry.unwrap();
+    ///     let mut regions = entry.sparse_regions().unwrap();
+    ///     while let Some(mut region) = regions.next_region().unwrap() {
+    ///         let mut data = Vec::new();
+    ///         region.read_to_end(&mut data).unwrap();
+    ///         println!("{} bytes at {}", data.len(), region.offset());
+    ///     }
+    /// }
+    /// ```
+    pub fn sparse_regions(&mut self) -> io::Result<SparseRegions<'_, 'a>> {
+        if !self.fields.is_unread() {
+            return Err(other(
+                "cannot read the regions of an entry after reading its contents",
+            ));
+        }
+        Ok(SparseRegions {
+            fields: &mut self.fields,
+            next: 0,
+            remaining: 0,
+        })
+    }
+
+    /// Returns the digest of the contents of this entry, if the archive is
+    /// computing digests with `Archive::set_digest`.
+    ///
+    /// This is `None` until all of the contents of the entry have been read,
+    /// including any holes of sparse files. Only the digest of the last entry
+    /// whose contents were all read is kept, so it's `None` again once that
+    /// happens for a later entry.
+    pub fn digest(&self) -> Option<Vec<u8>> {
+        let digests = self.fields.digests?;
+        let digest = digests.borrow().entry_digest(self.fields.header_pos);
+        digest
+    }
+
+    pub(crate) fn fields_mut(&mut self) -> &mut EntryFields<'a> {
+        &mut self.fields
+    }
+
     /// Writes this file to the specified location.
     ///
     /// This function will write the entire contents of this file into the
@@ -258,6 +443,15 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.unpack_xattrs = unpack_xattrs;
     }
 
+    /// Indicate whether POSIX ACLs recorded in `SCHILY.acl.*` records are
+    /// applied when unpacking this entry.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Linux with the `acl` feature.
+    pub fn set_unpack_acls(&mut self, unpack_acls: bool) {
+        self.fields.unpack_acls = unpack_acls;
+    }
+
     /// Indicate whether extended permissions (like suid on Unix) are preserved
     /// when unpacking this entry.
     ///
@@ -294,6 +488,20 @@ impl<'a> EntryFields<'a> {
         }
     }
 
+    /// Returns whether none of the contents of this entry have been read yet.
+    pub fn is_unread(&self) -> bool {
+        let remaining: u64 = self
+            .data
+            .iter()
+            .map(|io| match io {
+                EntryIo::Pad(io) => io.limit(),
+                EntryIo::Data(io) => io.limit(),
+                EntryIo::Buffered(io) => io.limit(),
+            })
+            .sum();
+        remaining == self.size
+    }
+
     pub fn read_all(&mut self) -> io::Result<Vec<u8>> {
         // Preallocate some data but don't let ourselves get too crazy now.
         let cap = cmp::min(self.size, 128 * 1024);
@@ -301,32 +509,16 @@ impl<'a> EntryFields<'a> {
         self.read_to_end(&mut v).map(|_| v)
     }
 
-    fn path(&self) -> io::Result<Cow<'_, Path>> {
+    pub fn path(&self) -> io::Result<Cow<'_, Path>> {
         bytes2path(self.path_bytes())
     }
 
-    fn path_bytes(&self) -> Cow<'_, [u8]> {
-        match self.long_pathname {
-            Some(ref bytes) => {
-                if let Some(&0) = bytes.last() {
-                    Cow::Borrowed(&bytes[..bytes.len() - 1])
-                } else {
-                    Cow::Borrowed(bytes)
-                }
-            }
-            None => {
-                if let Some(ref pax) = self.pax_extensions {
-                    let pax = PaxExtensions::new(pax)
-                        .filter_map(|f| f.ok())
-                        .find(|f| f.key_bytes() == b"path")
-                        .map(|f| f.value_bytes());
-                    if let Some(field) = pax {
-                        return Cow::Borrowed(field);
-                    }
-                }
-                self.header.path_bytes()
-            }
-        }
+    pub fn path_bytes(&self) -> Cow<'_, [u8]> {
+        entry_path_bytes(
+            &self.header,
+            self.long_pathname.as_deref(),
+            self.pax_extensions.as_deref(),
+        )
     }
 
     /// Gets the path in a "lossy" way, used for error reporting ONLY.
@@ -334,38 +526,169 @@ impl<'a> EntryFields<'a> {
         String::from_utf8_lossy(&self.path_bytes()).to_string()
     }
 
-    fn link_name(&self) -> io::Result<Option<Cow<'_, Path>>> {
+    pub fn link_name(&self) -> io::Result<Option<Cow<'_, Path>>> {
         match self.link_name_bytes() {
             Some(bytes) => bytes2path(bytes).map(Some),
             None => Ok(None),
         }
     }
 
-    fn link_name_bytes(&self) -> Option<Cow<'_, [u8]>> {
-        match self.long_linkname {
-            Some(ref bytes) => {
-                if let Some(&0) = bytes.last() {
-                    Some(Cow::Borrowed(&bytes[..bytes.len() - 1]))
-                } else {
-                    Some(Cow::Borrowed(bytes))
-                }
-            }
+    pub fn link_name_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        entry_link_name_bytes(
+            &self.header,
+            self.long_linkname.as_deref(),
+            self.pax_extensions.as_deref(),
+        )
+    }
+
+    pub fn mtime(&self) -> io::Result<SystemTime> {
+        match self.pax_time(PAX_MTIME)? {
+            Some(mtime) => Ok(mtime),
+            None => Ok(UNIX_EPOCH + Duration::from_secs(self.header.mtime()?)),
+        }
+    }
+
+    fn acl(&self, key: &str) -> io::Result<Option<Acl>> {
+        self.pax_value(key).map(Acl::parse).transpose()
+    }
+
+    /// Returns the id on this host of the user or group an ACL entry names.
+    ///
+    /// The name and recorded id of the entry are chosen between like those
+    /// of the owner of the entry, following the ownership policy, and the id
+    /// is mapped the same way. Entries which record no id are looked up by
+    /// name whatever the policy.
+    #[cfg(all(target_os = "linux", feature = "acl"))]
+    fn acl_id(&self, entry: &AclEntry) -> io::Result<u32> {
+        let user = entry.tag() == AclTag::User;
+        let kind = if user { "user" } else { "group" };
+        let id = match entry.id() {
+            Some(id) => self.policy_id(user, entry.name(), || Ok(id))?,
+            None => self.name_id(user, entry.name())?,
+        };
+        let id = match id {
+            Some(id) => self.map_id(user, id).ok_or_else(|| {
+                other(&format!(
+                    "{} {} in the ACL of `{}` is not mapped",
+                    kind,
+                    id,
+                    self.path_lossy()
+                ))
+            })?,
             None => {
-                if let Some(ref pax) = self.pax_extensions {
-                    let pax = PaxExtensions::new(pax)
-                        .filter_map(|f| f.ok())
-                        .find(|f| f.key_bytes() == b"linkpath")
-                        .map(|f| f.value_bytes());
-                    if let Some(field) = pax {
-                        return Some(Cow::Borrowed(field));
-                    }
-                }
-                self.header.link_name_bytes()
+                return Err(other(&format!(
+                    "unknown {} `{}` in the ACL of `{}`",
+                    kind,
+                    entry.name().unwrap_or_default(),
+                    self.path_lossy()
+                )))
             }
+        };
+        u32::try_from(id).map_err(|_| {
+            other(&format!(
+                "{} {} in the ACL of `{}` is out of range",
+                kind,
+                id,
+                self.path_lossy()
+            ))
+        })
+    }
+
+    /// Returns the pax records in effect for this entry, including those of
+    /// global headers.
+    fn pax_records(&self) -> Option<&[u8]> {
+        self.effective_extensions
+            .as_deref()
+            .or(self.pax_extensions.as_deref())
+    }
+
+    fn pax_value(&self, key: &str) -> Option<&[u8]> {
+        PaxExtensions::new(self.pax_records()?)
+            .filter_map(|f| f.ok())
+            .find(|f| f.key_bytes() == key.as_bytes())
+            .map(|f| f.value_bytes())
+    }
+
+    /// Returns the uid and gid to give the unpacked entry, or `None` if
+    /// ownerships aren't preserved. An id of `None` leaves it unchanged.
+    fn owner(&self) -> io::Result<Option<(Option<u64>, Option<u64>)>> {
+        if !self.preserve_ownerships {
+            return Ok(None);
+        }
+        fn name(bytes: Option<&[u8]>) -> Option<&str> {
+            bytes.and_then(|b| std::str::from_utf8(b).ok())
+        }
+
+        let uname = self
+            .pax_value(PAX_UNAME)
+            .or_else(|| self.header.username_bytes());
+        let gname = self
+            .pax_value(PAX_GNAME)
+            .or_else(|| self.header.groupname_bytes());
+        let uid = self.policy_id(true, name(uname), || self.header.uid())?;
+        let gid = self.policy_id(false, name(gname), || self.header.gid())?;
+
+        let map = |id: Option<u64>, user: bool, kind: &str| match id {
+            Some(id) => match self.map_id(user, id) {
+                Some(id) => Ok(Some(id)),
+                None => Err(other(&format!(
+                    "{} {} of `{}` is not mapped",
+                    kind,
+                    id,
+                    self.path_lossy()
+                ))),
+            },
+            None => Ok(None),
+        };
+        let uid = map(uid, true, "uid")?;
+        let gid = map(gid, false, "gid")?;
+        Ok(Some((uid, gid)))
+    }
+
+    /// Chooses between the id of the user (or group) called `name` and the
+    /// recorded `id` as the ownership policy says, before the id is mapped.
+    /// `None` is returned if neither is used.
+    fn policy_id(
+        &self,
+        user: bool,
+        name: Option<&str>,
+        id: impl FnOnce() -> io::Result<u64>,
+    ) -> io::Result<Option<u64>> {
+        let mut found = None;
+        if self.ownership_policy != OwnershipPolicy::Numeric {
+            found = self.name_id(user, name)?;
+        }
+        if found.is_none() && self.ownership_policy != OwnershipPolicy::NamesOnly {
+            found = Some(id()?);
+        }
+        Ok(found)
+    }
+
+    /// Looks up the id on this host of the user (or group) called `name`.
+    fn name_id(&self, user: bool, name: Option<&str>) -> io::Result<Option<u64>> {
+        match name.filter(|name| !name.is_empty()) {
+            Some(name) if user => self.id_lookup.uid_for_user(name),
+            Some(name) => self.id_lookup.gid_for_group(name),
+            None => Ok(None),
+        }
+    }
+
+    /// Maps a uid (or gid) of the archive to the host, if there's a map.
+    fn map_id(&self, user: bool, id: u64) -> Option<u64> {
+        match if user { self.uid_map } else { self.gid_map } {
+            Some(map) => map.to_outside(id),
+            None => Some(id),
+        }
+    }
+
+    pub fn pax_time(&self, key: &str) -> io::Result<Option<SystemTime>> {
+        match self.pax_records() {
+            Some(pax) => pax_extensions_time(pax, key),
+            None => Ok(None),
         }
     }
 
-    fn pax_extensions(&mut self) -> io::Result<Option<PaxExtensions<'_>>> {
+    pub fn pax_extensions(&mut self) -> io::Result<Option<PaxExtensions<'_>>> {
         if self.pax_extensions.is_none() {
             if !self.header.entry_type().is_pax_global_extensions()
                 && !self.header.entry_type().is_pax_local_extensions()
@@ -380,6 +703,38 @@ impl<'a> EntryFields<'a> {
     }
 
     fn unpack_in(&mut self, dst: &Path) -> io::Result<bool> {
+        match self.unpack_path()? {
+            Some(path) => self.unpack_in_path(dst, &path),
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the path this entry is unpacked to by default, or `None` if
+    /// stripping leading components leaves nothing of its path or of its
+    /// hard link target.
+    pub fn unpack_path(&self) -> io::Result<Option<PathBuf>> {
+        let path = self.path().map_err(|e| {
+            TarError::new(
+                format!("invalid path in entry header: {}", self.path_lossy()),
+                e,
+            )
+        })?;
+        if self.strip_components == 0 {
+            return Ok(Some(path.into_owned()));
+        }
+        if self.header.entry_type().is_hard_link() {
+            if let Some(src) = self.link_name()? {
+                if strip_components(&src, self.strip_components).is_none() {
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(strip_components(&path, self.strip_components))
+    }
+
+    /// Same as `unpack_in`, but extracts to `path` under `dst` rather than to
+    /// the path of this entry.
+    pub fn unpack_in_path(&mut self, dst: &Path, path: &Path) -> io::Result<bool> {
         // Notes regarding bsdtar 2.8.3 / libarchive 2.8.3:
         // * Leading '/'s are trimmed. For example, `///test` is treated as
         //   `test`.
@@ -394,28 +749,20 @@ impl<'a> EntryFields<'a> {
         // library, but we specially handle a few cases here as well.
 
         let mut file_dst = dst.to_path_buf();
-        {
-            let path = self.path().map_err(|e| {
-                TarError::new(
-                    format!("invalid path in entry header: {}", self.path_lossy()),
-                    e,
-                )
-            })?;
-            for part in path.components() {
-                match part {
-                    // Leading '/' characters, root paths, and '.'
-                    // components are just ignored and treated as "empty
-                    // components"
-                    Component::Prefix(..) | Component::RootDir | Component::CurDir => continue,
-
-                    // If any part of the filename is '..', then skip over
-                    // unpacking the file to prevent directory traversal
-                    // security issues.  See, e.g.: CVE-2001-1267,
-                    // CVE-2002-0399, CVE-2005-1918, CVE-2007-4131
-                    Component::ParentDir => return Ok(false),
-
-                    Component::Normal(part) => file_dst.push(part),
-                }
+        for part in path.components() {
+            match part {
+                // Leading '/' characters, root paths, and '.'
+                // components are just ignored and treated as "empty
+                // components"
+                Component::Prefix(..) | Component::RootDir | Component::CurDir => continue,
+
+                // If any part of the filename is '..', then skip over
+                // unpacking the file to prevent directory traversal
+                // security issues.  See, e.g.: CVE-2001-1267,
+                // CVE-2002-0399, CVE-2005-1918, CVE-2007-4131
+                Component::ParentDir => return Ok(false),
+
+                Component::Normal(part) => file_dst.push(part),
             }
         }
 
@@ -459,6 +806,47 @@ impl<'a> EntryFields<'a> {
         })
     }
 
+    /// Creates the character device, block device or FIFO described by this
+    /// entry at `dst`.
+    #[cfg(all(unix, not(target_arch = "wasm32")))]
+    fn unpack_special(&self, dst: &Path) -> io::Result<()> {
+        use std::ffi::CString;
+        use std::os::unix::prelude::*;
+
+        let path = CString::new(dst.as_os_str().as_bytes()).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                format!("path contains null character: {:?}", e),
+            )
+        })?;
+        let kind = self.header.entry_type();
+        let ret = if kind.is_fifo() {
+            unsafe { libc::mkfifo(path.as_ptr(), 0o600) }
+        } else {
+            let major = self.header.device_major()?.unwrap_or(0);
+            let minor = self.header.device_minor()?.unwrap_or(0);
+            let file_type = if kind.is_block_special() {
+                libc::S_IFBLK
+            } else {
+                libc::S_IFCHR
+            };
+            let dev = libc::makedev(major as _, minor as _);
+            unsafe { libc::mknod(path.as_ptr(), file_type | 0o600, dev) }
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(any(windows, target_arch = "wasm32"))]
+    fn unpack_special(&self, _dst: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "devices and FIFOs can only be created on Unix",
+        ))
+    }
+
     /// Returns access to the header of this entry in the archive.
     fn unpack(&mut self, target_base: Option<&Path>, dst: &Path) -> io::Result<Unpacked> {
         fn set_perms_ownerships(
@@ -467,11 +855,11 @@ impl<'a> EntryFields<'a> {
             header: &Header,
             mask: u32,
             perms: bool,
-            ownerships: bool,
+            owner: Option<(Option<u64>, Option<u64>)>,
         ) -> io::Result<()> {
             // ownerships need to be set first to avoid stripping SUID bits in the permissions ...
-            if ownerships {
-                set_ownerships(dst, &f, header.uid()?, header.gid()?)?;
+            if let Some((uid, gid)) = owner {
+                set_ownerships(dst, &f, uid, gid)?;
             }
             // ... then set permissions, SUID bits set here is kept
             if let Ok(mode) = header.mode() {
@@ -481,16 +869,29 @@ impl<'a> EntryFields<'a> {
             Ok(())
         }
 
-        fn get_mtime(header: &Header) -> Option<FileTime> {
-            header.mtime().ok().map(|mtime| {
+        fn get_times(entry: &EntryFields) -> Option<(FileTime, FileTime)> {
+            // A malformed pax time falls back to the time in the header.
+            let mtime = entry.mtime().or_else(|_| {
+                let secs = entry.header.mtime()?;
+                Ok::<_, io::Error>(UNIX_EPOCH + Duration::from_secs(secs))
+            });
+            mtime.ok().map(|mtime| {
                 // For some more information on this see the comments in
                 // `Header::fill_platform_from`, but the general idea is that
                 // we're trying to avoid 0-mtime files coming out of archives
                 // since some tools don't ingest them well. Perhaps one day
                 // when Cargo stops working with 0-mtime archives we can remove
                 // this.
-                let mtime = if mtime == 0 { 1 } else { mtime };
-                FileTime::from_unix_time(mtime as i64, 0)
+                let mtime = if mtime == UNIX_EPOCH {
+                    FileTime::from_unix_time(1, 0)
+                } else {
+                    FileTime::from_system_time(mtime)
+                };
+                let atime = match entry.pax_time(PAX_ATIME) {
+                    Ok(Some(atime)) => FileTime::from_system_time(atime),
+                    _ => mtime,
+                };
+                (atime, mtime)
             })
         }
 
@@ -504,8 +905,11 @@ impl<'a> EntryFields<'a> {
                 &self.header,
                 self.mask,
                 self.preserve_permissions,
-                self.preserve_ownerships,
+                self.owner()?,
             )?;
+            if self.unpack_acls {
+                set_acls(self, dst)?;
+            }
             return Ok(Unpacked::__Nonexhaustive);
         } else if kind.is_hard_link() || kind.is_symlink() {
             let src = match self.link_name()? {
@@ -539,7 +943,22 @@ impl<'a> EntryFields<'a> {
                     // links though they're canonicalized to their existing path
                     // so we need to validate at this time.
                     Some(p) => {
-                        let link_src = p.join(src);
+                        let link_src = if let Some(target) = &self.hard_link_target {
+                            p.join(target)
+                        } else if self.strip_components == 0 {
+                            p.join(src)
+                        } else {
+                            match strip_components(&src, self.strip_components) {
+                                Some(src) => p.join(src),
+                                None => {
+                                    return Err(other(&format!(
+                                        "hard link target {} has no more than {} components",
+                                        src.display(),
+                                        self.strip_components
+                                    )))
+                                }
+                            }
+                        };
                         self.validate_inside_dst(p, &link_src)?;
                         link_src
                     }
@@ -580,12 +999,12 @@ impl<'a> EntryFields<'a> {
                 // While permissions on symlinks are meaningless on most systems, the ownership
                 // of symlinks is important as it dictates the access control to the symlink
                 // itself.
-                if self.preserve_ownerships {
-                    set_ownerships(dst, &None, self.header.uid()?, self.header.gid()?)?;
+                if let Some((uid, gid)) = self.owner()? {
+                    set_ownerships(dst, &None, uid, gid)?;
                 }
                 if self.preserve_mtime {
-                    if let Some(mtime) = get_mtime(&self.header) {
-                        filetime::set_symlink_file_times(dst, mtime, mtime).map_err(|e| {
+                    if let Some((atime, mtime)) = get_times(self) {
+                        filetime::set_symlink_file_times(dst, atime, mtime).map_err(|e| {
                             TarError::new(format!("failed to set mtime for `{}`", dst.display()), e)
                         })?;
                     }
@@ -608,6 +1027,54 @@ impl<'a> EntryFields<'a> {
             fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
                 ::std::os::unix::fs::symlink(src, dst)
             }
+        } else if kind.is_character_special() || kind.is_block_special() || kind.is_fifo() {
+            if self.special_files == SpecialFilePolicy::Skip {
+                return Ok(Unpacked::__Nonexhaustive);
+            }
+            let created = self.unpack_special(dst).or_else(|err| {
+                if err.kind() == io::ErrorKind::AlreadyExists && self.overwrite {
+                    // remove dest and try once more
+                    fs::remove_file(dst).and_then(|()| self.unpack_special(dst))
+                } else {
+                    Err(err)
+                }
+            });
+            match created {
+                Ok(()) => {}
+                Err(err)
+                    if self.special_files == SpecialFilePolicy::CreateOrSkip
+                        && matches!(
+                            err.kind(),
+                            io::ErrorKind::PermissionDenied | io::ErrorKind::Unsupported
+                        ) =>
+                {
+                    return Ok(Unpacked::__Nonexhaustive);
+                }
+                Err(err) => {
+                    return Err(Error::new(
+                        err.kind(),
+                        format!("{} when creating {}", err, dst.display()),
+                    ));
+                }
+            }
+            set_perms_ownerships(
+                dst,
+                None,
+                &self.header,
+                self.mask,
+                self.preserve_permissions,
+                self.owner()?,
+            )?;
+            if self.preserve_mtime {
+                if let Some((atime, mtime)) = get_times(self) {
+                    // This sets the times without opening `dst`, which would block
+                    // for FIFOs.
+                    filetime::set_symlink_file_times(dst, atime, mtime).map_err(|e| {
+                        TarError::new(format!("failed to set mtime for `{}`", dst.display()), e)
+                    })?;
+                }
+            }
+            return Ok(Unpacked::__Nonexhaustive);
         } else if kind.is_pax_global_extensions()
             || kind.is_pax_local_extensions()
             || kind.is_gnu_longname()
@@ -627,7 +1094,7 @@ impl<'a> EntryFields<'a> {
                 &self.header,
                 self.mask,
                 self.preserve_permissions,
-                self.preserve_ownerships,
+                self.owner()?,
             )?;
             return Ok(Unpacked::__Nonexhaustive);
         }
@@ -668,6 +1135,9 @@ impl<'a> EntryFields<'a> {
                             return Err(other("failed to write entire file"));
                         }
                     }
+                    EntryIo::Buffered(mut d) => {
+                        io::copy(&mut d, &mut f)?;
+                    }
                     EntryIo::Pad(d) => {
                         // TODO: checked cast to i64
                         let to = SeekFrom::Current(d.limit() as i64);
@@ -691,8 +1161,8 @@ impl<'a> EntryFields<'a> {
         })?;
 
         if self.preserve_mtime {
-            if let Some(mtime) = get_mtime(&self.header) {
-                filetime::set_file_handle_times(&f, Some(mtime), Some(mtime)).map_err(|e| {
+            if let Some((atime, mtime)) = get_times(self) {
+                filetime::set_file_handle_times(&f, Some(atime), Some(mtime)).map_err(|e| {
                     TarError::new(format!("failed to set mtime for `{}`", dst.display()), e)
                 })?;
             }
@@ -703,18 +1173,21 @@ impl<'a> EntryFields<'a> {
             &self.header,
             self.mask,
             self.preserve_permissions,
-            self.preserve_ownerships,
+            self.owner()?,
         )?;
         if self.unpack_xattrs {
             set_xattrs(self, dst)?;
         }
+        if self.unpack_acls {
+            set_acls(self, dst)?;
+        }
         return Ok(Unpacked::File(f));
 
         fn set_ownerships(
             dst: &Path,
             f: &Option<&mut std::fs::File>,
-            uid: u64,
-            gid: u64,
+            uid: Option<u64>,
+            gid: Option<u64>,
         ) -> Result<(), TarError> {
             _set_ownerships(dst, f, uid, gid).map_err(|e| {
                 TarError::new(
@@ -734,17 +1207,24 @@ impl<'a> EntryFields<'a> {
         fn _set_ownerships(
             dst: &Path,
             f: &Option<&mut std::fs::File>,
-            uid: u64,
-            gid: u64,
+            uid: Option<u64>,
+            gid: Option<u64>,
         ) -> io::Result<()> {
             use std::os::unix::prelude::*;
 
-            let uid: libc::uid_t = uid.try_into().map_err(|_| {
-                io::Error::new(io::ErrorKind::Other, format!("UID {} is too large!", uid))
-            })?;
-            let gid: libc::gid_t = gid.try_into().map_err(|_| {
-                io::Error::new(io::ErrorKind::Other, format!("GID {} is too large!", gid))
-            })?;
+            // An id of -1 leaves it unchanged.
+            let uid: libc::uid_t = match uid {
+                Some(uid) => uid.try_into().map_err(|_| {
+                    io::Error::new(io::ErrorKind::Other, format!("UID {} is too large!", uid))
+                })?,
+                None => libc::uid_t::MAX,
+            };
+            let gid: libc::gid_t = match gid {
+                Some(gid) => gid.try_into().map_err(|_| {
+                    io::Error::new(io::ErrorKind::Other, format!("GID {} is too large!", gid))
+                })?,
+                None => libc::gid_t::MAX,
+            };
             match f {
                 Some(f) => unsafe {
                     let fd = f.as_raw_fd();
@@ -775,8 +1255,8 @@ impl<'a> EntryFields<'a> {
         fn _set_ownerships(
             _: &Path,
             _: &Option<&mut std::fs::File>,
-            _: u64,
-            _: u64,
+            _: Option<u64>,
+            _: Option<u64>,
         ) -> io::Result<()> {
             Ok(())
         }
@@ -862,9 +1342,9 @@ impl<'a> EntryFields<'a> {
             use std::ffi::OsStr;
             use std::os::unix::prelude::*;
 
-            let exts = match me.pax_extensions() {
-                Ok(Some(e)) => e,
-                _ => return Ok(()),
+            let exts = match me.pax_records() {
+                Some(e) => PaxExtensions::new(e),
+                None => return Ok(()),
             };
             let exts = exts
                 .filter_map(|e| e.ok())
@@ -899,6 +1379,36 @@ impl<'a> EntryFields<'a> {
         fn set_xattrs(_: &mut EntryFields, _: &Path) -> io::Result<()> {
             Ok(())
         }
+
+        // ACLs are set through the extended attributes Linux stores them in,
+        // after the permissions since they also set the group bits of the
+        // mode.
+        #[cfg(all(target_os = "linux", feature = "acl"))]
+        fn set_acls(me: &EntryFields, dst: &Path) -> io::Result<()> {
+            let mut acls = vec![("system.posix_acl_access", me.acl(PAX_SCHILYACLACCESS)?)];
+            if me.header.entry_type().is_dir() {
+                acls.push(("system.posix_acl_default", me.acl(PAX_SCHILYACLDEFAULT)?));
+            }
+            for (name, acl) in acls {
+                let acl = match acl {
+                    Some(acl) => acl,
+                    None => continue,
+                };
+                let value = acl.to_xattr(|entry| me.acl_id(entry))?;
+                xattr::set(dst, name, &value).map_err(|e| {
+                    TarError::new(
+                        format!("failed to set ACL `{}` on `{}`", acl, dst.display()),
+                        e,
+                    )
+                })?;
+            }
+            Ok(())
+        }
+
+        #[cfg(not(all(target_os = "linux", feature = "acl")))]
+        fn set_acls(_: &EntryFields, _: &Path) -> io::Result<()> {
+            Ok(())
+        }
     }
 
     fn ensure_dir_created(&self, dst: &Path, dir: &Path) -> io::Result<()> {
@@ -964,11 +1474,197 @@ impl<'a> Read for EntryFields<'a> {
     }
 }
 
+/// Reads the data of an entry which is stored in the archive, skipping over
+/// the holes of sparse files.
+pub struct StoredData<'b, 'a>(pub &'b mut EntryFields<'a>);
+
+impl Read for StoredData<'_, '_> {
+    fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
+        let data = &mut self.0.data;
+        loop {
+            match data.first_mut() {
+                Some(io @ (EntryIo::Data(_) | EntryIo::Buffered(_))) => match io.read(into)? {
+                    0 if !into.is_empty() => {
+                        data.remove(0);
+                    }
+                    n => return Ok(n),
+                },
+                Some(EntryIo::Pad(_)) => {
+                    data.remove(0);
+                }
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+impl<'b, 'a> SparseRegions<'b, 'a> {
+    /// Returns the next region of data, or `None` once all have been returned.
+    pub fn next_region(&mut self) -> io::Result<Option<SparseRegion<'_, 'b, 'a>>> {
+        if self.remaining > 0 {
+            let skipped = io::copy(
+                &mut StoredData(self.fields).take(self.remaining),
+                &mut io::sink(),
+            )?;
+            if skipped < self.remaining {
+                return Err(other("unexpected EOF in archive entry"));
+            }
+            self.remaining = 0;
+        }
+        let (offset, len) = match &self.fields.sparse {
+            Some(blocks) => match blocks.get(self.next) {
+                Some(&block) => block,
+                None => return Ok(None),
+            },
+            None if self.next == 0 => (0, self.fields.size),
+            None => return Ok(None),
+        };
+        self.next += 1;
+        self.remaining = len;
+        Ok(Some(SparseRegion {
+            regions: self,
+            offset,
+            len,
+        }))
+    }
+}
+
+impl SparseRegion<'_, '_, '_> {
+    /// Returns the offset of this region in the contents of the entry.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the length of this region.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns whether this region is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Read for SparseRegion<'_, '_, '_> {
+    fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
+        let regions = &mut *self.regions;
+        if regions.remaining == 0 || into.is_empty() {
+            return Ok(0);
+        }
+        let len = cmp::min(into.len() as u64, regions.remaining) as usize;
+        let n = StoredData(regions.fields).read(&mut into[..len])?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "unexpected EOF in archive entry",
+            ));
+        }
+        regions.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
 impl<'a> Read for EntryIo<'a> {
     fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
         match *self {
             EntryIo::Pad(ref mut io) => io.read(into),
             EntryIo::Data(ref mut io) => io.read(into),
+            EntryIo::Buffered(ref mut io) => io.read(into),
+        }
+    }
+}
+
+/// Returns the path of an entry, taking into account any GNU long name or
+/// pax extensions which precede its header.
+pub(crate) fn entry_path_bytes<'a>(
+    header: &'a Header,
+    long_pathname: Option<&'a [u8]>,
+    pax_extensions: Option<&'a [u8]>,
+) -> Cow<'a, [u8]> {
+    match long_pathname {
+        Some(bytes) => {
+            if let Some(&0) = bytes.last() {
+                Cow::Borrowed(&bytes[..bytes.len() - 1])
+            } else {
+                Cow::Borrowed(bytes)
+            }
+        }
+        None => {
+            if let Some(pax) = pax_extensions {
+                // The real name of a sparse file takes precedence, as the
+                // `path` of those is a placeholder for tools which don't
+                // know how to expand them.
+                let pax = PaxExtensions::new(pax)
+                    .filter_map(|f| f.ok())
+                    .find(|f| f.key_bytes() == PAX_GNUSPARSENAME.as_bytes())
+                    .or_else(|| {
+                        PaxExtensions::new(pax)
+                            .filter_map(|f| f.ok())
+                            .find(|f| f.key_bytes() == b"path")
+                    })
+                    .map(|f| f.value_bytes());
+                if let Some(field) = pax {
+                    return Cow::Borrowed(field);
+                }
+            }
+            header.path_bytes()
         }
     }
 }
+
+/// Returns the link name of an entry, taking into account any GNU long link
+/// or pax extensions which precede its header.
+pub(crate) fn entry_link_name_bytes<'a>(
+    header: &'a Header,
+    long_linkname: Option<&'a [u8]>,
+    pax_extensions: Option<&'a [u8]>,
+) -> Option<Cow<'a, [u8]>> {
+    match long_linkname {
+        Some(bytes) => {
+            if let Some(&0) = bytes.last() {
+                Some(Cow::Borrowed(&bytes[..bytes.len() - 1]))
+            } else {
+                Some(Cow::Borrowed(bytes))
+            }
+        }
+        None => {
+            if let Some(pax) = pax_extensions {
+                let pax = PaxExtensions::new(pax)
+                    .filter_map(|f| f.ok())
+                    .find(|f| f.key_bytes() == b"linkpath")
+                    .map(|f| f.value_bytes());
+                if let Some(field) = pax {
+                    return Some(Cow::Borrowed(field));
+                }
+            }
+            header.link_name_bytes()
+        }
+    }
+}
+
+/// Removes the first `n` normal components of `path`, returning `None` if no
+/// normal components remain.
+fn strip_components(path: &Path, n: usize) -> Option<PathBuf> {
+    let mut remaining = n;
+    let mut stripped = PathBuf::new();
+    let mut any_normal = false;
+    for part in path.components() {
+        match part {
+            Component::Normal(_) if remaining > 0 => remaining -= 1,
+            Component::Normal(part) => {
+                any_normal = true;
+                stripped.push(part);
+            }
+            // Keep `..` so it is still rejected when unpacking, while
+            // roots, prefixes and `.` are ignored there anyway.
+            Component::ParentDir => stripped.push(".."),
+            Component::Prefix(..) | Component::RootDir | Component::CurDir => {}
+        }
+    }
+    if any_normal {
+        Some(stripped)
+    } else {
+        None
+    }
+}
@@ -397,10 +397,11 @@ impl Header {
         self.set_path_inner(p.as_ref(), false, true)
     }
 
-    // Sets the truncated path for GNU header
+    // Sets the truncated path stored alongside a GNU long name entry or a pax
+    // `path` record.
     //
     // Same as set_path but skips some validations.
-    pub(crate) fn set_truncated_path_for_gnu_header<P: AsRef<Path>>(
+    pub(crate) fn set_truncated_path<P: AsRef<Path>>(
         &mut self,
         p: P,
         allow_absolute: bool,
@@ -411,17 +412,22 @@ impl Header {
     fn set_path_inner(
         &mut self,
         path: &Path,
-        is_truncated_gnu_long_path: bool,
+        is_truncated_long_path: bool,
         allow_absolute: bool,
     ) -> io::Result<()> {
         if let Some(ustar) = self.as_ustar_mut() {
-            return if allow_absolute {
-                ustar.set_path_absolute(path)
-            } else {
-                ustar.set_path(path)
-            };
+            if !is_truncated_long_path {
+                return if allow_absolute {
+                    ustar.set_path_absolute(path)
+                } else {
+                    ustar.set_path(path)
+                };
+            }
+            // Only the name field holds the truncated path, so make sure a
+            // stale prefix isn't prepended to it.
+            ustar.prefix = [0; 155];
         }
-        if is_truncated_gnu_long_path {
+        if is_truncated_long_path {
             copy_path_into_gnu_long(&mut self.as_old_mut().name, path, false, allow_absolute)
         } else {
             copy_path_into(&mut self.as_old_mut().name, path, false, allow_absolute)
@@ -0,0 +1,420 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::decoder::check_cksum;
+use crate::header::{bytes2path, path2bytes, BLOCK_SIZE};
+use crate::{other, Archive, Header};
+
+/// The bytes which start a serialized index.
+const MAGIC: &[u8; 8] = b"tar-idx\0";
+
+/// The version of the format written by `Index::write_to`.
+const VERSION: u32 = 1;
+
+/// An index of the entries of an archive, for reading any one of them from a
+/// seekable reader without scanning the archive again.
+///
+/// The index records where the header and contents of each entry are found in
+/// the archive, along with the layout of sparse files. It's built with a
+/// single pass over the archive, and can be saved alongside the archive with
+/// `write_to` and loaded again with `read_from`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::Read;
+/// use tar::{Archive, Index};
+///
+/// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+/// let index = Index::build(&mut ar).unwrap();
+/// let mut file = ar.into_inner();
+///
+/// let mut contents = String::new();
+/// index
+///     .get("foo/bar.txt")
+///     .unwrap()
+///     .open(&mut file)
+///     .unwrap()
+///     .read_to_string(&mut contents)
+///     .unwrap();
+/// ```
+pub struct Index {
+    entries: Vec<IndexEntry>,
+    paths: HashMap<PathBuf, usize>,
+}
+
+/// The location of an entry in an archive, as recorded by an [`Index`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    path: PathBuf,
+    header_pos: u64,
+    file_pos: u64,
+    size: u64,
+    sparse: Option<Vec<(u64, u64)>>,
+}
+
+/// The contents of an entry, opened from an [`IndexEntry`].
+///
+/// This reads the contents of the entry from the underlying reader, seeking as
+/// needed, and filling in the holes of sparse files with zeros. The contents
+/// can themselves be seeked.
+pub struct IndexedEntry<R> {
+    obj: R,
+    header: Header,
+    size: u64,
+    pos: u64,
+    obj_pos: Option<u64>,
+    // `(offset, length, position in the archive)` of each run of data
+    blocks: Vec<(u64, u64, u64)>,
+}
+
+impl Index {
+    /// Builds an index of the entries of `archive`.
+    ///
+    /// Like [`Archive::entries_with_seek`], the archive must be at position 0
+    /// and can't be read again afterwards, but its underlying reader can be
+    /// recovered with [`Archive::into_inner`] to open entries.
+    ///
+    /// If several entries have the same path the last of them is found by
+    /// `get`, as it's the one which would be unpacked.
+    pub fn build<R: Read + Seek>(archive: &mut Archive<R>) -> io::Result<Index> {
+        let mut index = Index {
+            entries: Vec::new(),
+            paths: HashMap::new(),
+        };
+        for entry in archive.entries_with_seek()? {
+            let entry = entry?;
+            index.push(IndexEntry {
+                path: entry.path()?.into_owned(),
+                header_pos: entry.raw_header_position(),
+                file_pos: entry.raw_file_position(),
+                size: entry.size(),
+                sparse: entry.sparse_map().map(|blocks| blocks.to_vec()),
+            });
+        }
+        Ok(index)
+    }
+
+    fn push(&mut self, entry: IndexEntry) {
+        self.paths.insert(entry.path.clone(), self.entries.len());
+        self.entries.push(entry);
+    }
+
+    /// Returns the entry with the given path, if any.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&IndexEntry> {
+        self.paths.get(path.as_ref()).map(|i| &self.entries[*i])
+    }
+
+    /// Returns all entries of the archive, in the order they appear in it.
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// Writes this index to `dst` in a compact binary format, which can be
+    /// loaded again with `read_from`.
+    ///
+    /// All integers are little endian, and the index is laid out as:
+    ///
+    /// * 8 bytes of magic, `tar-idx\0`.
+    /// * The format version as a `u32`, currently 1.
+    /// * The number of entries as a `u64`, followed by the entries in the
+    ///   order they appear in the archive. Each entry is:
+    ///   * The header position, file position and size of the entry, as
+    ///     three `u64`s.
+    ///   * The length of the path as a `u32`, followed by the bytes of the path
+    ///     with `/` as the separator.
+    ///   * The number of blocks of a sparse file as a `u64`, followed by the
+    ///     offset and length of each block as two `u64`s. Entries which aren't
+    ///     sparse have `u64::MAX` blocks.
+    ///
+    /// Readers reject versions they don't know, and the version is increased
+    /// whenever the layout changes.
+    pub fn write_to<W: Write>(&self, mut dst: W) -> io::Result<()> {
+        self._write_to(&mut dst)
+    }
+
+    fn _write_to(&self, dst: &mut dyn Write) -> io::Result<()> {
+        dst.write_all(MAGIC)?;
+        dst.write_all(&VERSION.to_le_bytes())?;
+        dst.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for entry in &self.entries {
+            for n in [entry.header_pos, entry.file_pos, entry.size] {
+                dst.write_all(&n.to_le_bytes())?;
+            }
+            let path = path2bytes(&entry.path)?;
+            let len = u32::try_from(path.len()).map_err(|_| other("path too long for index"))?;
+            dst.write_all(&len.to_le_bytes())?;
+            dst.write_all(&path)?;
+            match &entry.sparse {
+                Some(blocks) => {
+                    dst.write_all(&(blocks.len() as u64).to_le_bytes())?;
+                    for &(off, len) in blocks {
+                        dst.write_all(&off.to_le_bytes())?;
+                        dst.write_all(&len.to_le_bytes())?;
+                    }
+                }
+                None => dst.write_all(&u64::MAX.to_le_bytes())?,
+            }
+        }
+        dst.flush()
+    }
+
+    /// Reads an index written by `write_to` from `src`.
+    ///
+    /// An error is returned if `src` isn't an index, was written in a later
+    /// version of the format, or describes entries which couldn't be in an
+    /// archive, such as sparse blocks which overlap or extend past the end of
+    /// the file.
+    pub fn read_from<R: Read>(mut src: R) -> io::Result<Index> {
+        Index::_read_from(&mut src)
+    }
+
+    fn _read_from(src: &mut dyn Read) -> io::Result<Index> {
+        let mut magic = [0; 8];
+        src.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(other("not a tar index"));
+        }
+        let mut version = [0; 4];
+        src.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != VERSION {
+            return Err(other(&format!("unsupported tar index version {}", version)));
+        }
+
+        let mut index = Index {
+            entries: Vec::new(),
+            paths: HashMap::new(),
+        };
+        for _ in 0..read_u64(src)? {
+            let header_pos = read_u64(src)?;
+            let file_pos = read_u64(src)?;
+            let size = read_u64(src)?;
+            let mut len = [0; 4];
+            src.read_exact(&mut len)?;
+            let len = u64::from(u32::from_le_bytes(len));
+            let mut path = Vec::new();
+            src.take(len).read_to_end(&mut path)?;
+            if path.len() as u64 != len {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "failed to read path from tar index",
+                ));
+            }
+            let path = bytes2path(path.into())?.into_owned();
+            let sparse = match read_u64(src)? {
+                u64::MAX => None,
+                n => {
+                    let mut blocks = Vec::new();
+                    for _ in 0..n {
+                        blocks.push((read_u64(src)?, read_u64(src)?));
+                    }
+                    Some(blocks)
+                }
+            };
+            let entry = IndexEntry {
+                path,
+                header_pos,
+                file_pos,
+                size,
+                sparse,
+            };
+            entry.check()?;
+            index.push(entry);
+        }
+        Ok(index)
+    }
+}
+
+impl IndexEntry {
+    /// Checks that this entry, loaded from an untrusted index, describes
+    /// positions in an archive which `open` and reading can compute without
+    /// overflowing.
+    fn check(&self) -> io::Result<()> {
+        let invalid = |msg| Err(other(&format!("invalid tar index entry: {}", msg)));
+        if self.header_pos.checked_add(BLOCK_SIZE).is_none() {
+            return invalid("header position out of bounds");
+        }
+        let stored = match &self.sparse {
+            Some(blocks) => {
+                let mut cur = 0;
+                let mut stored = 0u64;
+                for &(off, len) in blocks {
+                    if off < cur {
+                        return invalid("out of order or overlapping sparse blocks");
+                    }
+                    cur = match off.checked_add(len) {
+                        Some(cur) => cur,
+                        None => return invalid("sparse block out of bounds"),
+                    };
+                    stored = match stored.checked_add(len) {
+                        Some(stored) => stored,
+                        None => return invalid("sparse block out of bounds"),
+                    };
+                }
+                if cur > self.size {
+                    return invalid("sparse blocks extend past the end of the file");
+                }
+                stored
+            }
+            None => self.size,
+        };
+        if self.file_pos.checked_add(stored).is_none() {
+            return invalid("file position out of bounds");
+        }
+        Ok(())
+    }
+
+    /// Returns the path name of this entry.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the starting position, in bytes, of the header of this entry in
+    /// the archive.
+    pub fn raw_header_position(&self) -> u64 {
+        self.header_pos
+    }
+
+    /// Returns the starting position, in bytes, of the file of this entry in
+    /// the archive.
+    pub fn raw_file_position(&self) -> u64 {
+        self.file_pos
+    }
+
+    /// Returns the size of the contents of this entry, including the holes of
+    /// sparse files.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the `(offset, length)` blocks of data of a sparse file, which
+    /// are stored back to back from `raw_file_position`, or `None` if this
+    /// entry isn't sparse.
+    pub fn sparse_map(&self) -> Option<&[(u64, u64)]> {
+        self.sparse.as_deref()
+    }
+
+    /// Opens the contents of this entry from `obj`, the archive which was
+    /// indexed.
+    ///
+    /// This reads the header of the entry to check that it's still there.
+    pub fn open<R: Read + Seek>(&self, mut obj: R) -> io::Result<IndexedEntry<R>> {
+        let mut header = Header::new_old();
+        obj.seek(SeekFrom::Start(self.header_pos))?;
+        obj.read_exact(header.as_mut_bytes())?;
+        check_cksum(&header)?;
+
+        let mut blocks = Vec::new();
+        match &self.sparse {
+            Some(sparse) => {
+                let mut pos = self.file_pos;
+                for &(off, len) in sparse {
+                    blocks.push((off, len, pos));
+                    pos += len;
+                }
+            }
+            None => blocks.push((0, self.size, self.file_pos)),
+        }
+        Ok(IndexedEntry {
+            obj,
+            header,
+            size: self.size,
+            pos: 0,
+            obj_pos: Some(self.header_pos + BLOCK_SIZE),
+            blocks,
+        })
+    }
+}
+
+impl<R> IndexedEntry<R> {
+    /// Returns access to the header of this entry in the archive.
+    ///
+    /// This is the header exactly as stored, without applying any pax
+    /// extensions which preceded it.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Returns the size of the contents of this entry, including the holes of
+    /// sparse files.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Unwraps this entry, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.obj
+    }
+}
+
+fn read_u64(src: &mut dyn Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    src.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+impl<R: Read + Seek> Read for IndexedEntry<R> {
+    fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.size || into.is_empty() {
+            return Ok(0);
+        }
+        let pos = self.pos;
+        let i = self
+            .blocks
+            .partition_point(|&(off, len, _)| off + len <= pos);
+        let n = match self.blocks.get(i) {
+            Some(&(off, len, start)) if off <= pos => {
+                let n = cmp::min(into.len() as u64, off + len - pos) as usize;
+                let at = start + (pos - off);
+                if self.obj_pos != Some(at) {
+                    self.obj_pos = None;
+                    self.obj.seek(SeekFrom::Start(at))?;
+                }
+                let n = self.obj.read(&mut into[..n])?;
+                if n == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "unexpected EOF in archive entry",
+                    ));
+                }
+                self.obj_pos = Some(at + n as u64);
+                n
+            }
+            next => {
+                let end = next.map_or(self.size, |&(off, _, _)| cmp::min(off, self.size));
+                let n = cmp::min(into.len() as u64, end - pos) as usize;
+                into[..n].fill(0);
+                n
+            }
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for IndexedEntry<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => (n, 0),
+            SeekFrom::End(n) => (self.size, n),
+            SeekFrom::Current(n) => (self.pos, n),
+        };
+        let pos = if offset >= 0 {
+            base.checked_add(offset as u64)
+        } else {
+            base.checked_sub(offset.unsigned_abs())
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
@@ -23,22 +23,42 @@
 
 use std::io::{Error, ErrorKind};
 
+pub use crate::acl::{Acl, AclEntry, AclTag};
 pub use crate::archive::{Archive, Entries};
-pub use crate::builder::{Builder, EntryWriter};
-pub use crate::entry::{Entry, Unpacked};
+#[cfg(feature = "tokio")]
+pub use crate::async_archive::{AsyncArchive, AsyncEntries, AsyncEntry};
+#[cfg(feature = "tokio")]
+pub use crate::async_builder::AsyncBuilder;
+pub use crate::builder::{ArchiveFormat, Builder, EntryWriter, XattrFilter};
+pub use crate::decoder::{Decoded, DecodedEntry, Decoder};
+#[cfg(feature = "sha2")]
+pub use crate::digest::Sha256;
+pub use crate::digest::{Digest, EntryDigest};
+pub use crate::entry::{Entry, SparseRegion, SparseRegions, SpecialFilePolicy, Unpacked};
 pub use crate::entry_type::EntryType;
 pub use crate::header::GnuExtSparseHeader;
 #[cfg(all(any(unix, windows), not(target_arch = "wasm32")))]
 pub use crate::header::DETERMINISTIC_TIMESTAMP;
 pub use crate::header::{GnuHeader, GnuSparseHeader, Header, HeaderMode, OldHeader, UstarHeader};
+pub use crate::index::{Index, IndexEntry, IndexedEntry};
+pub use crate::ownership::{IdLookup, IdMap, OwnershipPolicy, SystemIdLookup};
 pub use crate::pax::{PaxExtension, PaxExtensions};
 
+mod acl;
 mod archive;
+#[cfg(feature = "tokio")]
+mod async_archive;
+#[cfg(feature = "tokio")]
+mod async_builder;
 mod builder;
+mod decoder;
+mod digest;
 mod entry;
 mod entry_type;
 mod error;
 mod header;
+mod index;
+mod ownership;
 mod pax;
 
 fn other(msg: &str) -> Error {
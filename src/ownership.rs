@@ -0,0 +1,194 @@
+use std::io;
+#[cfg(all(unix, not(target_arch = "wasm32")))]
+use std::os::raw::{c_char, c_int};
+use std::str::FromStr;
+
+use crate::other;
+
+/// How the owner and group of unpacked entries are chosen when ownerships
+/// are preserved with `Archive::set_preserve_ownerships`.
+///
+/// Entries may record both numeric ids and user/group names (in the header or
+/// in pax `uname`/`gname` records). Names are resolved to ids on the host with
+/// the archive's [`IdLookup`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OwnershipPolicy {
+    /// Use the numeric uid and gid stored in the archive, ignoring names.
+    /// This is the default.
+    #[default]
+    Numeric,
+    /// Use the ids of the user and group names stored in the archive, falling
+    /// back to the numeric ids when a name is absent or unknown on this host.
+    /// This matches GNU tar's default behavior.
+    PreferNames,
+    /// Only use the ids of the user and group names stored in the archive.
+    /// When a name is absent or unknown the corresponding owner of the
+    /// unpacked file is left unchanged.
+    NamesOnly,
+}
+
+/// A database used to resolve user and group names to numeric ids.
+///
+/// The default implementation, [`SystemIdLookup`], consults the host's user
+/// database. Custom implementations can be installed with
+/// `Archive::set_id_lookup`.
+pub trait IdLookup {
+    /// Returns the uid of the user called `name`, or `None` if there is no
+    /// such user.
+    fn uid_for_user(&self, name: &str) -> io::Result<Option<u64>>;
+
+    /// Returns the gid of the group called `name`, or `None` if there is no
+    /// such group.
+    fn gid_for_group(&self, name: &str) -> io::Result<Option<u64>>;
+}
+
+/// An [`IdLookup`] backed by the system's user database (`getpwnam` and
+/// `getgrnam` on Unix).
+///
+/// On other platforms no names are ever found.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemIdLookup;
+
+#[cfg(all(unix, not(target_arch = "wasm32")))]
+impl IdLookup for SystemIdLookup {
+    fn uid_for_user(&self, name: &str) -> io::Result<Option<u64>> {
+        lookup(name, |name, buf| unsafe {
+            let mut pwd: libc::passwd = std::mem::zeroed();
+            let mut result = std::ptr::null_mut();
+            let ret = libc::getpwnam_r(name, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result);
+            (
+                ret,
+                if result.is_null() {
+                    None
+                } else {
+                    Some(pwd.pw_uid as u64)
+                },
+            )
+        })
+    }
+
+    fn gid_for_group(&self, name: &str) -> io::Result<Option<u64>> {
+        lookup(name, |name, buf| unsafe {
+            let mut grp: libc::group = std::mem::zeroed();
+            let mut result = std::ptr::null_mut();
+            let ret = libc::getgrnam_r(name, &mut grp, buf.as_mut_ptr(), buf.len(), &mut result);
+            (
+                ret,
+                if result.is_null() {
+                    None
+                } else {
+                    Some(grp.gr_gid as u64)
+                },
+            )
+        })
+    }
+}
+
+/// Calls one of the reentrant `get*nam_r` functions, growing the scratch
+/// buffer they need until the entry fits.
+#[cfg(all(unix, not(target_arch = "wasm32")))]
+fn lookup<F>(name: &str, mut f: F) -> io::Result<Option<u64>>
+where
+    F: FnMut(*const c_char, &mut [c_char]) -> (c_int, Option<u64>),
+{
+    let name = match std::ffi::CString::new(name) {
+        Ok(name) => name,
+        Err(_) => return Ok(None),
+    };
+    let mut buf = vec![0; 1024];
+    loop {
+        match f(name.as_ptr(), &mut buf) {
+            (0, id) => return Ok(id),
+            (libc::ERANGE, _) if buf.len() < 1024 * 1024 => {
+                let len = buf.len() * 2;
+                buf.resize(len, 0);
+            }
+            (err, _) => return Err(io::Error::from_raw_os_error(err)),
+        }
+    }
+}
+
+#[cfg(any(windows, target_arch = "wasm32"))]
+impl IdLookup for SystemIdLookup {
+    fn uid_for_user(&self, _: &str) -> io::Result<Option<u64>> {
+        Ok(None)
+    }
+
+    fn gid_for_group(&self, _: &str) -> io::Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+/// A mapping of user or group ids between an archive and the host, in the
+/// style of Linux's `/proc/self/uid_map`.
+///
+/// Each range maps `count` consecutive ids starting at `inside`, the ids as
+/// recorded in the archive (inside a container), to ids starting at
+/// `outside`, the ids on the host. Ids not covered by any range are
+/// unmapped.
+///
+/// # Examples
+///
+/// ```
+/// use tar::IdMap;
+///
+/// let map: IdMap = "0 100000 65536".parse().unwrap();
+/// assert_eq!(map.to_outside(1000), Some(101000));
+/// assert_eq!(map.to_inside(101000), Some(1000));
+/// assert_eq!(map.to_outside(65536), None);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IdMap {
+    ranges: Vec<(u64, u64, u64)>,
+}
+
+impl IdMap {
+    /// Creates an empty mapping, with every id unmapped.
+    pub fn new() -> IdMap {
+        IdMap::default()
+    }
+
+    /// Adds a range of `count` ids starting at `inside` in the archive and
+    /// at `outside` on the host.
+    pub fn add_range(&mut self, inside: u64, outside: u64, count: u64) {
+        self.ranges.push((inside, outside, count));
+    }
+
+    /// Maps an id recorded in an archive to the id on the host.
+    pub fn to_outside(&self, id: u64) -> Option<u64> {
+        self.ranges
+            .iter()
+            .find(|&&(inside, _, count)| id >= inside && id - inside < count)
+            .and_then(|&(inside, outside, _)| outside.checked_add(id - inside))
+    }
+
+    /// Maps an id on the host to the id to record in an archive.
+    pub fn to_inside(&self, id: u64) -> Option<u64> {
+        self.ranges
+            .iter()
+            .find(|&&(_, outside, count)| id >= outside && id - outside < count)
+            .and_then(|&(inside, outside, _)| inside.checked_add(id - outside))
+    }
+}
+
+/// Parses lines of `inside outside count` separated by whitespace, as found
+/// in `/proc/<pid>/uid_map` and `/proc/<pid>/gid_map`.
+impl FromStr for IdMap {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<IdMap> {
+        let mut map = IdMap::new();
+        for line in s.lines().filter(|line| !line.trim().is_empty()) {
+            let fields = line
+                .split_whitespace()
+                .map(|field| field.parse::<u64>())
+                .collect::<Result<Vec<_>, _>>();
+            match fields.as_deref() {
+                Ok([inside, outside, count]) => map.add_range(*inside, *outside, *count),
+                _ => return Err(other(&format!("invalid id map line `{}`", line))),
+            }
+        }
+        Ok(map)
+    }
+}
@@ -3,6 +3,7 @@ use std::io;
 use std::io::Write;
 use std::slice;
 use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::other;
 
@@ -22,6 +23,8 @@ pub const PAX_CHARSET: &str = "charset"; // Currently unused
 pub const PAX_COMMENT: &str = "comment"; // Currently unused
 
 pub const PAX_SCHILYXATTR: &str = "SCHILY.xattr.";
+pub const PAX_SCHILYACLACCESS: &str = "SCHILY.acl.access";
+pub const PAX_SCHILYACLDEFAULT: &str = "SCHILY.acl.default";
 
 // Keywords for GNU sparse files in a PAX extended header.
 pub const PAX_GNUSPARSE: &str = "GNU.sparse.";
@@ -84,6 +87,163 @@ pub fn pax_extensions_value(a: &[u8], key: &str) -> Option<u64> {
     None
 }
 
+/// Looks up a timestamp record such as `mtime` in a pax extended header.
+pub(crate) fn pax_extensions_time(a: &[u8], key: &str) -> io::Result<Option<SystemTime>> {
+    for extension in PaxExtensions::new(a) {
+        let extension = extension?;
+        if extension.key() != Ok(key) {
+            continue;
+        }
+        return extension
+            .value()
+            .map_err(|_| other("malformed pax timestamp"))
+            .and_then(parse_pax_time)
+            .map(Some);
+    }
+    Ok(None)
+}
+
+/// Parses a pax timestamp, a possibly negative decimal number of seconds
+/// since the epoch with an optional fractional part.
+///
+/// Digits of the fraction beyond nanosecond precision are ignored.
+pub(crate) fn parse_pax_time(value: &str) -> io::Result<SystemTime> {
+    fn digits(s: &str) -> bool {
+        !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+    }
+
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value),
+    };
+    let (secs, frac) = match value.find('.') {
+        Some(i) => (&value[..i], &value[i + 1..]),
+        None => (value, "0"),
+    };
+    if !digits(secs) || !digits(frac) {
+        return Err(other("malformed pax timestamp"));
+    }
+    let secs = secs
+        .parse::<u64>()
+        .map_err(|_| other("pax timestamp out of range"))?;
+    let nanos = frac
+        .bytes()
+        .chain(std::iter::repeat(b'0'))
+        .take(9)
+        .fold(0, |n, b| n * 10 + u32::from(b - b'0'));
+    let duration = Duration::new(secs, nanos);
+    let time = if negative {
+        UNIX_EPOCH.checked_sub(duration)
+    } else {
+        UNIX_EPOCH.checked_add(duration)
+    };
+    time.ok_or_else(|| other("pax timestamp out of range"))
+}
+
+/// Formats a timestamp as a pax record value, the inverse of
+/// `parse_pax_time`.
+pub(crate) fn format_pax_time(time: SystemTime) -> String {
+    let (sign, duration) = match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => ("", duration),
+        Err(e) => ("-", e.duration()),
+    };
+    let secs = duration.as_secs();
+    let nanos = duration.subsec_nanos();
+    if nanos == 0 {
+        return format!("{}{}", sign, secs);
+    }
+    let frac = format!("{:09}", nanos);
+    format!("{}{}.{}", sign, secs, frac.trim_end_matches('0'))
+}
+
+/// The layout of a sparse file as described by the `GNU.sparse.*` records
+/// written by GNU tar for pax archives.
+pub(crate) struct PaxSparse {
+    /// The `(offset, length)` data blocks of the file, or `None` for format
+    /// 1.0 where the map is stored at the start of the entry's data instead.
+    pub(crate) map: Option<Vec<(u64, u64)>>,
+    /// The size of the file once expanded, if recorded.
+    pub(crate) real_size: Option<u64>,
+}
+
+/// Parses the sparse file records of a pax extended header, returning `None`
+/// if the entry isn't a sparse file.
+///
+/// Formats 0.0 (repeated `GNU.sparse.offset`/`GNU.sparse.numbytes` records),
+/// 0.1 (a single `GNU.sparse.map` record) and 1.0 (`GNU.sparse.major` and
+/// `GNU.sparse.minor` records) are recognized.
+pub(crate) fn pax_sparse(a: &[u8]) -> io::Result<Option<PaxSparse>> {
+    fn parse(value: &str) -> io::Result<u64> {
+        value
+            .parse()
+            .map_err(|_| other("malformed pax sparse file record"))
+    }
+
+    let mut major = None;
+    let mut minor = None;
+    let mut real_size = None;
+    let mut numblocks = None;
+    let mut map = None;
+    let mut offsets = Vec::new();
+    let mut lengths = Vec::new();
+    for extension in PaxExtensions::new(a) {
+        let extension = extension?;
+        let key = match extension.key() {
+            Ok(key) if key.starts_with(PAX_GNUSPARSE) => key,
+            _ => continue,
+        };
+        let value = extension
+            .value()
+            .map_err(|_| other("malformed pax sparse file record"))?;
+        match key {
+            PAX_GNUSPARSEMAJOR => major = Some(parse(value)?),
+            PAX_GNUSPARSEMINOR => minor = Some(parse(value)?),
+            PAX_GNUSPARSESIZE | PAX_GNUSPARSEREALSIZE => real_size = Some(parse(value)?),
+            PAX_GNUSPARSENUMBLOCKS => numblocks = Some(parse(value)?),
+            PAX_GNUSPARSEOFFSET => offsets.push(parse(value)?),
+            PAX_GNUSPARSENUMBYTES => lengths.push(parse(value)?),
+            PAX_GNUSPARSEMAP => map = Some(value),
+            _ => {}
+        }
+    }
+
+    let blocks = match (major, minor) {
+        (Some(1), Some(0)) => {
+            return Ok(Some(PaxSparse {
+                map: None,
+                real_size,
+            }))
+        }
+        (Some(_), _) | (_, Some(_)) => {
+            return Err(other("unsupported pax sparse file format version"))
+        }
+        (None, None) => match map {
+            Some("") => Vec::new(),
+            Some(map) => {
+                let values = map.split(',').map(parse).collect::<io::Result<Vec<_>>>()?;
+                if values.len() % 2 != 0 {
+                    return Err(other("malformed pax sparse file map"));
+                }
+                values.chunks(2).map(|c| (c[0], c[1])).collect()
+            }
+            None if numblocks.is_some() => {
+                if offsets.len() != lengths.len() {
+                    return Err(other("malformed pax sparse file map"));
+                }
+                offsets.into_iter().zip(lengths).collect()
+            }
+            None => return Ok(None),
+        },
+    };
+    if numblocks.map_or(false, |n| n != blocks.len() as u64) {
+        return Err(other("mismatch in pax sparse file block count"));
+    }
+    Ok(Some(PaxSparse {
+        map: Some(blocks),
+        real_size,
+    }))
+}
+
 impl<'entry> Iterator for PaxExtensions<'entry> {
     type Item = io::Result<PaxExtension<'entry>>;
 
@@ -147,6 +307,60 @@ impl<'entry> PaxExtension<'entry> {
     }
 }
 
+/// Accumulates the PAX records describing a single archive entry.
+#[derive(Default)]
+pub(crate) struct PaxBuilder {
+    data: Vec<u8>,
+}
+
+impl PaxBuilder {
+    pub(crate) fn new() -> PaxBuilder {
+        PaxBuilder::default()
+    }
+
+    /// Appends a `key=value` record, prefixed with its own length as the
+    /// format requires.
+    pub(crate) fn add(&mut self, key: &str, value: &[u8]) {
+        let mut len_len = 1;
+        let mut max_len = 10;
+        let rest_len = 3 + key.len() + value.len();
+        while rest_len + len_len >= max_len {
+            len_len += 1;
+            max_len *= 10;
+        }
+        let len = rest_len + len_len;
+        self.data
+            .extend_from_slice(format!("{} {}=", len, key).as_bytes());
+        self.data.extend_from_slice(value);
+        self.data.push(b'\n');
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns whether a record for `key` has been added.
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        PaxExtensions::new(&self.data)
+            .filter_map(|e| e.ok())
+            .any(|e| e.key_bytes() == key.as_bytes())
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Creates the header of the extended header entry carrying these
+    /// records.
+    pub(crate) fn header(&self) -> crate::Header {
+        let mut header = crate::Header::new_ustar();
+        header.set_size(self.data.len() as u64);
+        header.set_entry_type(crate::EntryType::XHeader);
+        header.set_cksum();
+        header
+    }
+}
+
 /// Extension trait for `Builder` to append PAX extended headers.
 impl<T: Write> crate::Builder<T> {
     /// Append PAX extended headers to the archive.
@@ -159,37 +373,49 @@ impl<T: Write> crate::Builder<T> {
         headers: impl IntoIterator<Item = (&'key str, &'value [u8])>,
     ) -> Result<(), io::Error> {
         // Store the headers formatted before write
-        let mut data: Vec<u8> = Vec::new();
-
-        // For each key in headers, convert into a sized space and add it to data.
-        // This will then be written in the file
+        let mut pax = PaxBuilder::new();
         for (key, value) in headers {
-            let mut len_len = 1;
-            let mut max_len = 10;
-            let rest_len = 3 + key.len() + value.len();
-            while rest_len + len_len >= max_len {
-                len_len += 1;
-                max_len *= 10;
-            }
-            let len = rest_len + len_len;
-            write!(&mut data, "{} {}=", len, key)?;
-            data.extend_from_slice(value);
-            data.push(b'\n');
+            pax.add(key, value);
         }
 
         // Ignore the header append if it's empty.
-        if data.is_empty() {
+        if pax.is_empty() {
             return Ok(());
         }
 
-        // Create a header of type XHeader, set the size to the length of the
-        // data, set the entry type to XHeader, and set the checksum
-        // then append the header and the data to the archive.
-        let mut header = crate::Header::new_ustar();
-        let data_as_bytes: &[u8] = &data;
-        header.set_size(data_as_bytes.len() as u64);
-        header.set_entry_type(crate::EntryType::XHeader);
+        // Create a header of type XHeader sized to the formatted data, then
+        // append the header and the data to the archive.
+        self.append(&pax.header(), pax.as_bytes())
+    }
+
+    /// Append a PAX global extended header to the archive, whose records
+    /// describe all entries appended after it.
+    ///
+    /// This can be used to stamp an archive with metadata such as the commit
+    /// it was built from, which `git archive` stores in a `comment` record.
+    /// Readers apply these records to each following entry unless the
+    /// entry's own records override them, and expose them through
+    /// `Entry::pax_global_extensions`.
+    ///
+    /// Nothing is appended if there are no records.
+    pub fn append_pax_global_extensions<'key, 'value>(
+        &mut self,
+        headers: impl IntoIterator<Item = (&'key str, &'value [u8])>,
+    ) -> Result<(), io::Error> {
+        let mut pax = PaxBuilder::new();
+        for (key, value) in headers {
+            pax.add(key, value);
+        }
+        if pax.is_empty() {
+            return Ok(());
+        }
+
+        // Readers which don't know about global headers extract them as a
+        // file, so give it the same name as `git archive` does.
+        let mut header = pax.header();
+        header.set_entry_type(crate::EntryType::XGlobalHeader);
+        header.set_path("pax_global_header")?;
         header.set_cksum();
-        self.append(&header, data_as_bytes)
+        self.append(&header, pax.as_bytes())
     }
 }
@@ -1,3 +1,7 @@
+// Tests are built with dev-dependencies that already require a newer
+// toolchain than the crate's MSRV.
+#![allow(clippy::incompatible_msrv)]
+
 extern crate filetime;
 extern crate tar;
 extern crate tempfile;
@@ -14,7 +18,9 @@ use std::process::Command;
 use filetime::FileTime;
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
-use tar::{Archive, Builder, Entries, Entry, EntryType, Header, HeaderMode};
+use tar::{
+    Archive, Builder, Decoded, Decoder, Entries, Entry, EntryType, Header, HeaderMode, Index,
+};
 use tempfile::{Builder as TempBuilder, TempDir};
 
 /// A reader wrapper that returns partial results from `read()` to exercise
@@ -481,6 +487,283 @@ fn no_xattrs() {
     );
 }
 
+#[test]
+#[cfg(all(unix, feature = "xattr"))]
+fn builder_xattrs() {
+    let td = TempBuilder::new()
+        .prefix("tar-rs")
+        .tempdir_in("/var/tmp")
+        .unwrap();
+    let file = td.path().join("file");
+    let dir = td.path().join("dir");
+    fs::write(&file, b"hello").unwrap();
+    fs::create_dir(&dir).unwrap();
+    for (path, name) in [(&file, "user.a"), (&file, "user.b"), (&dir, "user.c")] {
+        xattr::set(path, name, name.as_bytes()).unwrap();
+    }
+    // Names which can't be pax record keys are skipped.
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        xattr::set(&file, OsStr::from_bytes(b"user.\xff"), b"x").unwrap();
+    }
+
+    let mut filter = tar::XattrFilter::new();
+    filter.include("user.");
+    filter.exclude("user.b");
+    let mut ar = Builder::new(Vec::new());
+    ar.xattrs(filter);
+    ar.append_path_with_name(&file, "file").unwrap();
+    ar.append_dir("dir", &dir).unwrap();
+    // Files opened by the caller have their attributes captured too.
+    ar.append_file("opened", &mut File::open(&file).unwrap())
+        .unwrap();
+    let data = ar.into_inner().unwrap();
+
+    let mut ar = Archive::new(&data[..]);
+    let mut xattrs = Vec::new();
+    for entry in ar.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let mut names = Vec::new();
+        for ext in entry.pax_extensions().unwrap().into_iter().flatten() {
+            let ext = ext.unwrap();
+            let key = ext.key().unwrap();
+            if let Some(name) = key.strip_prefix("SCHILY.xattr.") {
+                assert_eq!(ext.value_bytes(), name.as_bytes());
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        xattrs.push((entry.path().unwrap().into_owned(), names));
+    }
+    assert_eq!(
+        xattrs,
+        [
+            (PathBuf::from("file"), vec!["user.a".to_string()]),
+            (PathBuf::from("dir"), vec!["user.c".to_string()]),
+            (PathBuf::from("opened"), vec!["user.a".to_string()]),
+        ]
+    );
+
+    // Without a filter nothing is captured.
+    let mut ar = Builder::new(Vec::new());
+    ar.append_path_with_name(&file, "file").unwrap();
+    let data = ar.into_inner().unwrap();
+    let mut ar = Archive::new(&data[..]);
+    let mut entry = ar.entries().unwrap().next().unwrap().unwrap();
+    assert!(entry.pax_extensions().unwrap().is_none());
+}
+
+#[test]
+fn acl_records() {
+    let mut ar = Builder::new(Vec::new());
+    ar.append_pax_extensions([
+        (
+            "SCHILY.acl.access",
+            b"user::rwx,user:lisa:r--:1000,group::r-x,mask::r-x,other::---".as_slice(),
+        ),
+        (
+            "SCHILY.acl.default",
+            b"u::rwx,g:100:r-x #effective:r-x,m::rx,o::-",
+        ),
+    ])
+    .unwrap();
+    let mut header = Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_size(0);
+    ar.append_data(&mut header, "dir", io::empty()).unwrap();
+    ar.append_pax_extensions([("SCHILY.acl.access", b"user:lisa".as_slice())])
+        .unwrap();
+    ar.append_data(&mut header, "bad", io::empty()).unwrap();
+    let data = ar.into_inner().unwrap();
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = ar.entries().unwrap();
+    let entry = entries.next().unwrap().unwrap();
+    let access = entry.acl_access().unwrap().unwrap();
+    let lisa = &access.entries()[1];
+    assert_eq!(lisa.tag(), tar::AclTag::User);
+    assert_eq!(lisa.name(), Some("lisa"));
+    assert_eq!(lisa.id(), Some(1000));
+    assert_eq!(lisa.perms(), 0o4);
+    assert_eq!(
+        access.to_string(),
+        "user::rwx,user:lisa:r--:1000,group::r-x,mask::r-x,other::---"
+    );
+    let default = entry.acl_default().unwrap().unwrap();
+    let group = &default.entries()[1];
+    assert_eq!(group.tag(), tar::AclTag::Group);
+    assert_eq!((group.name(), group.id()), (None, Some(100)));
+    assert_eq!(
+        default.to_string(),
+        "user::rwx,group:100:r-x,mask::r-x,other::---"
+    );
+
+    let acl = tar::Acl::parse(b"# file: dir\nuser::rwx\nother::r-x\n").unwrap();
+    assert_eq!(acl.to_string(), "user::rwx,other::r-x");
+
+    let entry = entries.next().unwrap().unwrap();
+    assert!(entry.acl_access().is_err());
+    assert!(entry.acl_default().unwrap().is_none());
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "acl"))]
+fn builder_and_unpack_acls() {
+    // Linux stores ACLs in extended attributes, as a version followed by
+    // (tag, perms, id) entries.
+    fn acl(entries: &[(u16, u16, u32)]) -> Vec<u8> {
+        let mut value = 2u32.to_le_bytes().to_vec();
+        for (tag, perms, id) in entries {
+            value.extend_from_slice(&tag.to_le_bytes());
+            value.extend_from_slice(&perms.to_le_bytes());
+            value.extend_from_slice(&id.to_le_bytes());
+        }
+        value
+    }
+    let none = u32::MAX;
+    let access = acl(&[
+        (0x01, 7, none),
+        (0x02, 4, 1000),
+        (0x04, 5, none),
+        (0x10, 5, none),
+        (0x20, 5, none),
+    ]);
+    let default = acl(&[
+        (0x01, 7, none),
+        (0x04, 5, none),
+        (0x08, 7, 100),
+        (0x10, 7, none),
+        (0x20, 0, none),
+    ]);
+
+    let td = TempBuilder::new()
+        .prefix("tar-rs")
+        .tempdir_in("/var/tmp")
+        .unwrap();
+    let dir = td.path().join("dir");
+    let file = td.path().join("file");
+    fs::create_dir(&dir).unwrap();
+    fs::write(&file, b"hello").unwrap();
+    xattr::set(&dir, "system.posix_acl_access", &access).unwrap();
+    xattr::set(&dir, "system.posix_acl_default", &default).unwrap();
+    xattr::set(&file, "system.posix_acl_access", &access).unwrap();
+
+    let mut ar = Builder::new(Vec::new());
+    ar.acls(true);
+    // ACLs aren't captured a second time as raw extended attributes.
+    ar.xattrs(tar::XattrFilter::new());
+    ar.append_dir("dir", &dir).unwrap();
+    ar.append_path_with_name(&file, "file").unwrap();
+    let data = ar.into_inner().unwrap();
+
+    let mut ar = Archive::new(&data[..]);
+    for entry in ar.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        for ext in entry.pax_extensions().unwrap().unwrap() {
+            let key = ext.unwrap().key().unwrap().to_string();
+            assert!(!key.starts_with("SCHILY.xattr.system.posix_acl"), "{}", key);
+        }
+        let access = entry.acl_access().unwrap().unwrap();
+        assert_eq!(
+            access.to_string(),
+            "user::rwx,user:1000:r--,group::r-x,mask::r-x,other::r-x"
+        );
+        let default = entry.acl_default().unwrap().map(|acl| acl.to_string());
+        if entry.header().entry_type().is_dir() {
+            assert_eq!(
+                default.as_deref(),
+                Some("user::rwx,group::r-x,group:100:rwx,mask::rwx,other::---")
+            );
+        } else {
+            assert_eq!(default, None);
+        }
+    }
+
+    let out = td.path().join("out");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_unpack_acls(true);
+    ar.unpack(&out).unwrap();
+    for (path, name, value) in [
+        ("dir", "system.posix_acl_access", &access),
+        ("dir", "system.posix_acl_default", &default),
+        ("file", "system.posix_acl_access", &access),
+    ] {
+        let found = xattr::get(out.join(path), name).unwrap();
+        assert_eq!(found.as_ref(), Some(value), "{} of {}", name, path);
+    }
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "acl"))]
+fn unpack_acl_ids() {
+    use tar::{IdLookup, IdMap, OwnershipPolicy};
+
+    struct FakeLookup;
+
+    impl IdLookup for FakeLookup {
+        fn uid_for_user(&self, name: &str) -> io::Result<Option<u64>> {
+            Ok(if name == "alice" { Some(2000) } else { None })
+        }
+
+        fn gid_for_group(&self, name: &str) -> io::Result<Option<u64>> {
+            Ok(if name == "staff" { Some(200) } else { None })
+        }
+    }
+
+    // Unpacks a file with the access ACL `acl`, returning the ids of its
+    // named users and groups.
+    let ids = |acl: &str, policy| -> io::Result<Vec<u32>> {
+        let mut ar = Builder::new(Vec::new());
+        ar.append_pax_extensions([("SCHILY.acl.access", acl.as_bytes())])
+            .unwrap();
+        let mut header = Header::new_gnu();
+        header.set_size(0);
+        header.set_mode(0o644);
+        ar.append_data(&mut header, "file", io::empty()).unwrap();
+        let data = ar.into_inner().unwrap();
+
+        let td = TempBuilder::new()
+            .prefix("tar-rs")
+            .tempdir_in("/var/tmp")
+            .unwrap();
+        let mut ar = Archive::new(&data[..]);
+        ar.set_unpack_acls(true);
+        ar.set_ownership_policy(policy);
+        ar.set_id_lookup(FakeLookup);
+        ar.set_uid_map("0 10000 10000\n".parse::<IdMap>().unwrap());
+        ar.set_gid_map("0 20000 10000\n".parse::<IdMap>().unwrap());
+        ar.unpack(td.path())?;
+        let value = xattr::get(td.path().join("file"), "system.posix_acl_access")?.unwrap();
+        // A version, followed by (tag, perms, id) entries.
+        Ok(value[4..]
+            .chunks(8)
+            .filter(|e| matches!(u16::from_le_bytes([e[0], e[1]]), 0x02 | 0x08))
+            .map(|e| u32::from_le_bytes([e[4], e[5], e[6], e[7]]))
+            .collect())
+    };
+
+    // Names are only looked up as the ownership policy says, and the ids are
+    // mapped like the owner of the entry either way.
+    let acl = "user::rwx,user:alice:r--:1000,group::r-x,group:staff:r-x:100,\
+               mask::r-x,other::r-x";
+    assert_eq!(ids(acl, OwnershipPolicy::Numeric).unwrap(), [11000, 20100]);
+    assert_eq!(
+        ids(acl, OwnershipPolicy::PreferNames).unwrap(),
+        [12000, 20200]
+    );
+    assert_eq!(
+        ids(acl, OwnershipPolicy::NamesOnly).unwrap(),
+        [12000, 20200]
+    );
+
+    // Entries without an id are looked up by name whatever the policy.
+    let acl = "user::rwx,user:alice:r--,user:bob:r--:5,group::r-x,mask::r-x,other::r-x";
+    assert_eq!(ids(acl, OwnershipPolicy::Numeric).unwrap(), [10005, 12000]);
+    let acl = "user::rwx,user:bob:r--,group::r-x,mask::r-x,other::r-x";
+    assert!(ids(acl, OwnershipPolicy::Numeric).is_err());
+}
+
 #[test]
 fn writing_and_extracting_directories() {
     let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
@@ -689,6 +972,205 @@ fn append_dir_all_blank_dest() {
         .unwrap_or(false));
 }
 
+#[test]
+fn append_dir_all_with_filter() {
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+
+    let base_dir = td.path().join("base");
+    fs::create_dir_all(base_dir.join("sub/.git")).unwrap();
+    fs::create_dir_all(base_dir.join("target")).unwrap();
+    fs::write(base_dir.join("file1"), b"file1").unwrap();
+    fs::write(base_dir.join("file1.bak"), b"file1").unwrap();
+    fs::write(base_dir.join("sub/file2"), b"file2").unwrap();
+    fs::write(base_dir.join("sub/.git/config"), b"config").unwrap();
+    fs::write(base_dir.join("target/out"), b"out").unwrap();
+
+    let mut seen = Vec::new();
+    let mut ar = Builder::new(Vec::new());
+    ar.append_dir_all_with("foobar", &base_dir, |path, meta| {
+        seen.push(path.to_path_buf());
+        if meta.is_dir() {
+            !path.ends_with(".git") && path != Path::new("target")
+        } else {
+            path.extension().map_or(true, |ext| ext != "bak")
+        }
+    })
+    .unwrap();
+    let data = ar.into_inner().unwrap();
+
+    // Pruned directories aren't descended into.
+    assert!(seen.contains(&PathBuf::from("sub/.git")));
+    assert!(!seen.contains(&PathBuf::from("sub/.git/config")));
+    assert!(!seen.contains(&PathBuf::from("target/out")));
+
+    let mut ar = Archive::new(Cursor::new(data));
+    let mut paths = ar
+        .entries()
+        .unwrap()
+        .map(|entry| entry.unwrap().path().unwrap().into_owned())
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(
+        paths,
+        ["foobar", "foobar/file1", "foobar/sub", "foobar/sub/file2"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn append_dir_all_with_skips_dangling_symlinks() {
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+    let base_dir = td.path().join("base");
+    fs::create_dir(&base_dir).unwrap();
+    fs::write(base_dir.join("file"), b"file").unwrap();
+    std::os::unix::fs::symlink("missing", base_dir.join("dangling")).unwrap();
+
+    // Symlinks are followed by default, but only once the filter has seen
+    // them, as symlinks.
+    let mut ar = Builder::new(Vec::new());
+    ar.append_dir_all_with("foo", &base_dir, |_, meta| !meta.file_type().is_symlink())
+        .unwrap();
+    let data = ar.into_inner().unwrap();
+
+    let mut ar = Archive::new(&data[..]);
+    let mut paths = ar
+        .entries()
+        .unwrap()
+        .map(|entry| entry.unwrap().path().unwrap().into_owned())
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(paths, [PathBuf::from("foo"), PathBuf::from("foo/file")]);
+}
+
+#[test]
+fn append_dir_all_sorted() {
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+
+    // Build the same tree twice, creating files in a different order.
+    let names = ["b", "a/z", "a/B", "c/d/e", "a-b", "C"];
+    let mut archives = Vec::new();
+    for (i, order) in [names.to_vec(), names.iter().rev().cloned().collect()]
+        .iter()
+        .enumerate()
+    {
+        let base_dir = td.path().join(format!("base{}", i));
+        for name in order {
+            let path = base_dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, name).unwrap();
+        }
+
+        let mut ar = Builder::new(Vec::new());
+        ar.mode(HeaderMode::Deterministic);
+        ar.sort_by_name(true);
+        ar.append_dir_all("", &base_dir).unwrap();
+        archives.push(ar.into_inner().unwrap());
+    }
+    assert!(archives[0] == archives[1]);
+
+    let mut ar = Archive::new(&archives[0][..]);
+    let paths = ar
+        .entries()
+        .unwrap()
+        .map(|entry| entry.unwrap().path().unwrap().into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        ["C", "a", "a/B", "a/z", "a-b", "b", "c", "c/d", "c/d/e"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn append_dir_all_hard_links() {
+    use std::os::unix::fs::MetadataExt;
+
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+    let base_dir = td.path().join("base");
+    fs::create_dir(&base_dir).unwrap();
+    fs::write(base_dir.join("a"), b"data").unwrap();
+    fs::hard_link(base_dir.join("a"), base_dir.join("b")).unwrap();
+
+    let mut ar = Builder::new(Vec::new());
+    ar.sort_by_name(true);
+    ar.append_dir_all("foo", &base_dir).unwrap();
+    let data = ar.into_inner().unwrap();
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = ar.entries().unwrap();
+    assert_eq!(&*entries.next().unwrap().unwrap().path_bytes(), b"foo/");
+    let a = entries.next().unwrap().unwrap();
+    assert_eq!(a.header().entry_type(), EntryType::Regular);
+    assert_eq!(a.size(), 4);
+    let b = entries.next().unwrap().unwrap();
+    assert_eq!(&*b.path_bytes(), b"foo/b");
+    assert_eq!(b.header().entry_type(), EntryType::Link);
+    assert_eq!(b.size(), 0);
+    assert_eq!(&*b.link_name_bytes().unwrap(), b"foo/a");
+    assert!(entries.next().is_none());
+
+    let dst = td.path().join("dst");
+    Archive::new(&data[..]).unpack(&dst).unwrap();
+    let a = fs::metadata(dst.join("foo/a")).unwrap();
+    let b = fs::metadata(dst.join("foo/b")).unwrap();
+    assert_eq!(a.ino(), b.ino());
+    assert_eq!(fs::read(dst.join("foo/b")).unwrap(), b"data");
+
+    // With detection off both links are archived in full.
+    let mut ar = Builder::new(Vec::new());
+    ar.detect_hard_links(false);
+    ar.append_dir_all("foo", &base_dir).unwrap();
+    let data = ar.into_inner().unwrap();
+    let mut ar = Archive::new(&data[..]);
+    for entry in ar.entries().unwrap() {
+        let entry = entry.unwrap();
+        assert_ne!(entry.header().entry_type(), EntryType::Link);
+    }
+}
+
+#[test]
+fn open_append() {
+    fn contents(data: &[u8]) -> Vec<(PathBuf, String)> {
+        let mut ar = Archive::new(data);
+        ar.entries()
+            .unwrap()
+            .map(|e| {
+                let mut e = e.unwrap();
+                let mut s = String::new();
+                e.read_to_string(&mut s).unwrap();
+                (e.path().unwrap().into_owned(), s)
+            })
+            .collect()
+    }
+
+    let mut ar = Builder::new(Cursor::new(Vec::new()));
+    let mut header = Header::new_gnu();
+    header.set_size(5);
+    ar.append_data(&mut header, "a", &b"hello"[..]).unwrap();
+    let mut archives = vec![ar.into_inner().unwrap().into_inner()];
+    // Archives written by other tools may be padded with extra zero blocks.
+    archives.push(tar!("directory.tar").to_vec());
+
+    for archive in archives {
+        let mut expected = contents(&archive);
+        expected.push((PathBuf::from("appended"), "world".to_string()));
+
+        let mut ar = Builder::open_append(Cursor::new(archive)).unwrap();
+        let mut header = Header::new_gnu();
+        header.set_size(5);
+        ar.append_data(&mut header, "appended", &b"world"[..])
+            .unwrap();
+        let data = ar.into_inner().unwrap().into_inner();
+        assert_eq!(contents(&data), expected);
+    }
+}
+
 #[test]
 fn append_dir_all_does_not_work_on_non_directory() {
     let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
@@ -1133,43 +1615,375 @@ fn pax_simple_write() {
 }
 
 #[test]
-fn pax_path() {
-    let mut ar = Archive::new(random_cursor_reader(tar!("pax2.tar")));
-    let mut entries = ar.entries().unwrap();
+fn pax_global_extensions() {
+    fn append(ar: &mut Builder<Vec<u8>>, kind: tar::EntryType, path: &str, data: &[u8]) {
+        let mut header = Header::new_ustar();
+        header.set_entry_type(kind);
+        header.set_size(data.len() as u64);
+        header.set_uid(7);
+        header.set_mtime(1);
+        ar.append_data(&mut header, path, data).unwrap();
+    }
+
+    let mut ar = Builder::new(Vec::new());
+    append(
+        &mut ar,
+        tar::EntryType::XGlobalHeader,
+        "pax_global_header",
+        b"18 comment=abc123\n12 uid=1234\n20 mtime=1000000000\n",
+    );
+    append(&mut ar, tar::EntryType::Regular, "a", b"");
+    append(&mut ar, tar::EntryType::XHeader, "b.pax", b"10 uid=99\n");
+    append(&mut ar, tar::EntryType::Regular, "b", b"");
+    // An empty value removes the global record.
+    append(
+        &mut ar,
+        tar::EntryType::XGlobalHeader,
+        "pax_global_header",
+        b"7 uid=\n",
+    );
+    append(&mut ar, tar::EntryType::Regular, "c", b"");
+    let data = ar.into_inner().unwrap();
+
+    let records = |exts: Option<tar::PaxExtensions<'_>>| {
+        exts.unwrap()
+            .map(|e| {
+                let e = e.unwrap();
+                format!("{}={}", e.key().unwrap(), e.value().unwrap())
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut ar = Archive::new(&data[..]);
+    let mut found = Vec::new();
+    let mut headers = Vec::new();
+    for entry in ar.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        // Global headers are still yielded, with their own records.
+        if entry.header().entry_type().is_pax_global_extensions() {
+            headers.push(records(entry.pax_extensions().unwrap()));
+            continue;
+        }
+        let global = records(entry.pax_global_extensions());
+        // Only the entry's own records are returned as its extensions.
+        let local = entry.pax_extensions().unwrap().map(|e| records(Some(e)));
+        found.push((
+            entry.path().unwrap().into_owned(),
+            entry.header().uid().unwrap(),
+            entry.header().mtime().unwrap(),
+            local,
+            global,
+        ));
+    }
+    let global = |records: &[&str]| records.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            (
+                PathBuf::from("a"),
+                1234,
+                1000000000,
+                None,
+                global(&["comment=abc123", "uid=1234", "mtime=1000000000"])
+            ),
+            (
+                PathBuf::from("b"),
+                99,
+                1000000000,
+                Some(global(&["uid=99"])),
+                global(&["comment=abc123", "uid=1234", "mtime=1000000000"])
+            ),
+            (
+                PathBuf::from("c"),
+                7,
+                1000000000,
+                None,
+                global(&["comment=abc123", "mtime=1000000000"])
+            ),
+        ]
+    );
+    assert_eq!(
+        headers,
+        [
+            global(&["comment=abc123", "uid=1234", "mtime=1000000000"]),
+            global(&["uid="]),
+        ]
+    );
 
+    // Raw iteration yields the global headers untouched.
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = ar.entries().unwrap().raw(true);
     let first = entries.next().unwrap().unwrap();
-    assert!(first.path().unwrap().ends_with("aaaaaaaaaaaaaaa"));
+    assert!(first.header().entry_type().is_pax_global_extensions());
+    let second = entries.next().unwrap().unwrap();
+    assert_eq!(second.header().uid().unwrap(), 7);
+    assert!(second.pax_global_extensions().is_none());
 }
 
 #[test]
-fn pax_linkpath() {
-    let mut ar = Archive::new(random_cursor_reader(tar!("pax2.tar")));
-    let mut links = ar.entries().unwrap().skip(3).take(2);
+fn pax_global_extensions_keep_framing() {
+    // Global `size`, `path` and `linkpath` records would describe every
+    // member after them, so they're not applied.
+    let data = fs::read(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/archives/pax_global.tar"
+    ))
+    .unwrap();
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = ar.entries().unwrap();
+    let mut global = entries.next().unwrap().unwrap();
+    assert!(global.header().entry_type().is_pax_global_extensions());
+    let mut contents = String::new();
+    global.read_to_string(&mut contents).unwrap();
+    assert!(contents.contains(" size=1024\n"));
+    drop(global);
+
+    let mut found = Vec::new();
+    for entry in entries {
+        let mut entry = entry.unwrap();
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents).unwrap();
+        assert!(entry.pax_extensions().unwrap().is_none());
+        let comment = entry
+            .pax_global_extensions()
+            .unwrap()
+            .map(|e| e.unwrap())
+            .find(|e| e.key() == Ok("comment"))
+            .map(|e| e.value().unwrap().to_string());
+        assert_eq!(comment.as_deref(), Some("abc123"));
+        found.push((
+            entry.path().unwrap().into_owned(),
+            entry.link_name().unwrap().map(|l| l.into_owned()),
+            entry.size(),
+            contents,
+        ));
+    }
+    assert_eq!(
+        found,
+        [
+            (PathBuf::from("a.txt"), None, 4, b"aaaa".to_vec()),
+            (PathBuf::from("b.txt"), None, 8, b"bbbbbbbb".to_vec()),
+        ]
+    );
+}
 
-    let long_symlink = links.next().unwrap().unwrap();
-    let link_name = long_symlink.link_name().unwrap().unwrap();
-    assert!(link_name.to_str().unwrap().len() > 99);
-    assert!(link_name.ends_with("bbbbbbbbbbbbbbb"));
+#[test]
+fn append_pax_global_extensions() {
+    let mut ar = Builder::new(Vec::new());
+    ar.append_pax_global_extensions([("comment", b"abc123".as_slice())])
+        .unwrap();
+    let mut header = Header::new_gnu();
+    header.set_size(5);
+    ar.append_data(&mut header, "file", &b"hello"[..]).unwrap();
+    ar.append_pax_global_extensions([("comment", b"def456".as_slice()), ("build", b"7")])
+        .unwrap();
+    ar.append_pax_global_extensions(std::iter::empty()).unwrap();
+    let data = ar.into_inner().unwrap();
 
-    let long_hardlink = links.next().unwrap().unwrap();
-    let link_name = long_hardlink.link_name().unwrap().unwrap();
-    assert!(link_name.to_str().unwrap().len() > 99);
-    assert!(link_name.ends_with("ccccccccccccccc"));
+    let records = |exts: Option<tar::PaxExtensions<'_>>| {
+        exts.unwrap()
+            .map(|e| {
+                let e = e.unwrap();
+                (e.key().unwrap().to_string(), e.value().unwrap().to_string())
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = ar.entries().unwrap();
+    let mut entry = entries.next().unwrap().unwrap();
+    assert!(entry.header().entry_type().is_pax_global_extensions());
+    assert_eq!(
+        records(entry.pax_extensions().unwrap()),
+        [("comment".to_string(), "abc123".to_string())]
+    );
+    drop(entry);
+    let entry = entries.next().unwrap().unwrap();
+    assert_eq!(entry.path().unwrap(), Path::new("file"));
+    assert_eq!(
+        records(entry.pax_global_extensions()),
+        [("comment".to_string(), "abc123".to_string())]
+    );
+    drop(entry);
+    let entry = entries.next().unwrap().unwrap();
+    assert!(entry.header().entry_type().is_pax_global_extensions());
+    drop(entry);
+    assert!(entries.next().is_none());
+    assert_eq!(
+        records(entries.pax_global_extensions()),
+        [
+            ("comment".to_string(), "def456".to_string()),
+            ("build".to_string(), "7".to_string())
+        ]
+    );
+
+    // Only the two non-empty global headers are written.
+    let mut ar = Archive::new(&data[..]);
+    let raw = ar
+        .entries()
+        .unwrap()
+        .raw(true)
+        .map(|e| {
+            let e = e.unwrap();
+            (e.header().entry_type(), e.path().unwrap().into_owned())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        raw,
+        [
+            (
+                tar::EntryType::XGlobalHeader,
+                PathBuf::from("pax_global_header")
+            ),
+            (tar::EntryType::Regular, PathBuf::from("file")),
+            (
+                tar::EntryType::XGlobalHeader,
+                PathBuf::from("pax_global_header")
+            ),
+        ]
+    );
 }
 
 #[test]
-fn long_name_trailing_nul() {
-    let mut b = Builder::new(Vec::<u8>::new());
-
-    let mut h = Header::new_gnu();
-    h.set_path("././@LongLink").unwrap();
-    h.set_size(4);
-    h.set_entry_type(EntryType::new(b'L'));
-    h.set_cksum();
-    b.append(&h, "foo\0".as_bytes()).unwrap();
-    let mut h = Header::new_gnu();
+fn pax_times() {
+    use std::time::{Duration, UNIX_EPOCH};
 
-    h.set_path("bar").unwrap();
+    let mut ar = Builder::new(Vec::new());
+    ar.append_pax_extensions([
+        ("mtime", b"1234567890.123456789".as_slice()),
+        ("atime", b"1000000000.25"),
+        ("ctime", b"-1.5"),
+    ])
+    .unwrap();
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    ar.append_data(&mut header, "a", io::empty()).unwrap();
+    let data = ar.into_inner().unwrap();
+
+    let mut ar = Archive::new(&data[..]);
+    let e = ar.entries().unwrap().next().unwrap().unwrap();
+    assert_eq!(e.header().mtime().unwrap(), 1234567890);
+    assert_eq!(
+        e.mtime().unwrap(),
+        UNIX_EPOCH + Duration::new(1234567890, 123456789)
+    );
+    assert_eq!(
+        e.atime().unwrap(),
+        Some(UNIX_EPOCH + Duration::new(1000000000, 250_000_000))
+    );
+    assert_eq!(
+        e.ctime().unwrap(),
+        Some(UNIX_EPOCH - Duration::new(1, 500_000_000))
+    );
+
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+    let mut ar = Archive::new(&data[..]);
+    ar.set_preserve_mtime(true);
+    ar.unpack(td.path()).unwrap();
+    let meta = fs::metadata(td.path().join("a")).unwrap();
+    let mtime = FileTime::from_last_modification_time(&meta);
+    assert_eq!(mtime, FileTime::from_unix_time(1234567890, 123456789));
+    let atime = FileTime::from_last_access_time(&meta);
+    assert_eq!(atime, FileTime::from_unix_time(1000000000, 250_000_000));
+}
+
+#[test]
+fn malformed_pax_times() {
+    let mut ar = Builder::new(Vec::new());
+    ar.append_pax_extensions([("mtime", b"soon".as_slice()), ("atime", b"1.x")])
+        .unwrap();
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    header.set_mtime(1234567890);
+    ar.append_data(&mut header, "a", io::empty()).unwrap();
+    let data = ar.into_inner().unwrap();
+
+    let mut ar = Archive::new(&data[..]);
+    let e = ar.entries().unwrap().next().unwrap().unwrap();
+    assert!(e.mtime().is_err());
+
+    // Unpacking falls back to the mtime in the header.
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+    let mut ar = Archive::new(&data[..]);
+    ar.set_preserve_mtime(true);
+    ar.unpack(td.path()).unwrap();
+    let meta = fs::metadata(td.path().join("a")).unwrap();
+    let mtime = FileTime::from_last_modification_time(&meta);
+    assert_eq!(mtime, FileTime::from_unix_time(1234567890, 0));
+    let atime = FileTime::from_last_access_time(&meta);
+    assert_eq!(atime, FileTime::from_unix_time(1234567890, 0));
+}
+
+#[test]
+fn build_pax_timestamps() {
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+    let path = td.path().join("a");
+    fs::write(&path, b"a").unwrap();
+    let mtime = FileTime::from_unix_time(1234567890, 123456789);
+    let atime = FileTime::from_unix_time(1000000000, 500_000_000);
+    filetime::set_file_times(&path, atime, mtime).unwrap();
+
+    let mut ar = Builder::new(Vec::new());
+    ar.pax_timestamps(true);
+    ar.append_path_with_name(&path, "a").unwrap();
+    let data = ar.into_inner().unwrap();
+
+    let mut ar = Archive::new(&data[..]);
+    let e = ar.entries().unwrap().next().unwrap().unwrap();
+    assert_eq!(e.header().mtime().unwrap(), 1234567890);
+    assert_eq!(FileTime::from_system_time(e.mtime().unwrap()), mtime);
+    assert_eq!(
+        FileTime::from_system_time(e.atime().unwrap().unwrap()),
+        atime
+    );
+    #[cfg(unix)]
+    assert!(e.ctime().unwrap().is_some());
+
+    let out = td.path().join("out");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_preserve_mtime(true);
+    ar.unpack(&out).unwrap();
+    let meta = fs::metadata(out.join("a")).unwrap();
+    assert_eq!(FileTime::from_last_modification_time(&meta), mtime);
+    assert_eq!(FileTime::from_last_access_time(&meta), atime);
+}
+
+#[test]
+fn pax_path() {
+    let mut ar = Archive::new(random_cursor_reader(tar!("pax2.tar")));
+    let mut entries = ar.entries().unwrap();
+
+    let first = entries.next().unwrap().unwrap();
+    assert!(first.path().unwrap().ends_with("aaaaaaaaaaaaaaa"));
+}
+
+#[test]
+fn pax_linkpath() {
+    let mut ar = Archive::new(random_cursor_reader(tar!("pax2.tar")));
+    let mut links = ar.entries().unwrap().skip(3).take(2);
+
+    let long_symlink = links.next().unwrap().unwrap();
+    let link_name = long_symlink.link_name().unwrap().unwrap();
+    assert!(link_name.to_str().unwrap().len() > 99);
+    assert!(link_name.ends_with("bbbbbbbbbbbbbbb"));
+
+    let long_hardlink = links.next().unwrap().unwrap();
+    let link_name = long_hardlink.link_name().unwrap().unwrap();
+    assert!(link_name.to_str().unwrap().len() > 99);
+    assert!(link_name.ends_with("ccccccccccccccc"));
+}
+
+#[test]
+fn long_name_trailing_nul() {
+    let mut b = Builder::new(Vec::<u8>::new());
+
+    let mut h = Header::new_gnu();
+    h.set_path("././@LongLink").unwrap();
+    h.set_size(4);
+    h.set_entry_type(EntryType::new(b'L'));
+    h.set_cksum();
+    b.append(&h, "foo\0".as_bytes()).unwrap();
+    let mut h = Header::new_gnu();
+
+    h.set_path("bar").unwrap();
     h.set_size(6);
     h.set_entry_type(EntryType::file());
     h.set_cksum();
@@ -1228,6 +2042,102 @@ fn long_linkname_gnu() {
     }
 }
 
+#[test]
+fn long_names_pax_format() {
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+    let long = "a".repeat(150);
+    fs::write(td.path().join(&long), b"hello").unwrap();
+
+    let mut b = Builder::new(Vec::<u8>::new());
+    b.format(tar::ArchiveFormat::Pax);
+    let mut h = Header::new_ustar();
+    h.set_size(4);
+    let path = format!("dir/{}", long);
+    b.append_data(&mut h, &path, &b"data"[..]).unwrap();
+    let mut h = Header::new_ustar();
+    h.set_entry_type(tar::EntryType::Symlink);
+    h.set_size(0);
+    b.append_link(&mut h, "link", &path).unwrap();
+    b.append_path_with_name(td.path().join(&long), &long)
+        .unwrap();
+    let contents = b.into_inner().unwrap();
+
+    // No GNU extension entries, just pax headers followed by ustar headers.
+    let mut a = Archive::new(&contents[..]);
+    let types = a
+        .entries()
+        .unwrap()
+        .raw(true)
+        .map(|e| {
+            let e = e.unwrap();
+            assert!(e.header().as_ustar().is_some());
+            e.header().entry_type()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        types,
+        [
+            tar::EntryType::XHeader,
+            tar::EntryType::Regular,
+            tar::EntryType::XHeader,
+            tar::EntryType::Symlink,
+            tar::EntryType::XHeader,
+            tar::EntryType::Regular,
+        ]
+    );
+
+    let mut a = Archive::new(&contents[..]);
+    let mut entries = a.entries().unwrap();
+    let mut e = entries.next().unwrap().unwrap();
+    assert_eq!(e.path().unwrap().to_str().unwrap(), path);
+    let mut s = String::new();
+    e.read_to_string(&mut s).unwrap();
+    assert_eq!(s, "data");
+    let e = entries.next().unwrap().unwrap();
+    assert_eq!(e.path().unwrap().to_str().unwrap(), "link");
+    assert_eq!(e.link_name().unwrap().unwrap().to_str().unwrap(), path);
+    let e = entries.next().unwrap().unwrap();
+    assert_eq!(e.path().unwrap().to_str().unwrap(), long);
+    assert!(entries.next().is_none());
+}
+
+#[test]
+fn large_ids_pax_format() {
+    let mut b = Builder::new(Vec::<u8>::new());
+    b.format(tar::ArchiveFormat::Pax);
+    let mut h = Header::new_ustar();
+    h.set_size(4);
+    h.set_uid(5_000_000);
+    h.set_gid(6_000_000);
+    h.set_mtime(1 << 40);
+    b.append_data(&mut h, "a", &b"data"[..]).unwrap();
+    let contents = b.into_inner().unwrap();
+
+    // The values are pax records, and the header only has octal numbers.
+    let mut a = Archive::new(&contents[..]);
+    let mut entries = a.entries().unwrap().raw(true);
+    let mut pax = entries.next().unwrap().unwrap();
+    let mut records = String::new();
+    pax.read_to_string(&mut records).unwrap();
+    assert_eq!(
+        records,
+        "15 uid=5000000\n15 gid=6000000\n23 mtime=1099511627776\n"
+    );
+    drop(pax);
+    let e = entries.next().unwrap().unwrap();
+    let old = e.header().as_old();
+    for field in [&old.uid[..], &old.gid[..], &old.mtime[..]] {
+        assert!(field.iter().all(|b| matches!(b, b'0'..=b'7' | 0)));
+    }
+    drop(entries);
+
+    let mut a = Archive::new(&contents[..]);
+    let e = a.entries().unwrap().next().unwrap().unwrap();
+    assert_eq!(e.header().uid().unwrap(), 5_000_000);
+    assert_eq!(e.header().gid().unwrap(), 6_000_000);
+    assert_eq!(e.header().mtime().unwrap(), 1 << 40);
+}
+
 #[test]
 fn linkname_literal() {
     for t in [tar::EntryType::Symlink, tar::EntryType::Link] {
@@ -1443,9 +2353,144 @@ fn sparse_with_trailing() {
 }
 
 #[test]
-#[allow(clippy::option_map_unit_fn)]
+fn reading_pax_sparse() {
+    let archives = [
+        tar!("pax-sparse-0.0.tar"),
+        tar!("pax-sparse-0.1.tar"),
+        tar!("pax-sparse-1.0.tar"),
+    ];
+    for archive in archives {
+        let rdr = random_cursor_reader(archive);
+        let mut ar = Archive::new(rdr);
+        let mut entries = ar.entries().unwrap();
+
+        let mut a = entries.next().unwrap().unwrap();
+        assert_eq!(a.path().unwrap(), Path::new("sparse.txt"));
+        assert_eq!(a.size(), 20000);
+        let mut s = String::new();
+        a.read_to_string(&mut s).unwrap();
+        assert_eq!(s.len(), 20000);
+        assert!(s[..0x1000].chars().all(|x| x == '\u{0}'));
+        assert_eq!(&s[0x1000..0x1000 + 6], "hello\n");
+        assert!(s[0x1000 + 6..0x3000].chars().all(|x| x == '\u{0}'));
+        assert_eq!(&s[0x3000..0x3000 + 6], "world\n");
+        assert!(s[0x3000 + 6..].chars().all(|x| x == '\u{0}'));
+
+        assert!(entries.next().is_none());
+    }
+}
+
+#[test]
+fn extract_pax_sparse() {
+    let rdr = random_cursor_reader(tar!("pax-sparse-1.0.tar"));
+    let mut ar = Archive::new(rdr);
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+    ar.unpack(td.path()).unwrap();
+
+    let s = fs::read(td.path().join("sparse.txt")).unwrap();
+    assert_eq!(s.len(), 20000);
+    assert!(s[..0x1000].iter().all(|x| *x == 0));
+    assert_eq!(&s[0x1000..0x1000 + 6], b"hello\n");
+    assert!(s[0x1000 + 6..0x3000].iter().all(|x| *x == 0));
+    assert_eq!(&s[0x3000..0x3000 + 6], b"world\n");
+    assert!(s[0x3000 + 6..].iter().all(|x| *x == 0));
+}
+
+#[test]
+fn sparse_regions() {
+    let rdr = random_cursor_reader(tar!("pax-sparse-1.0.tar"));
+    let mut ar = Archive::new(rdr);
+    let mut entries = ar.entries().unwrap();
+    let mut a = entries.next().unwrap().unwrap();
+    let map = [(0x1000, 0x1000), (0x3000, 0x1000), (20000, 0)];
+    assert_eq!(a.sparse_map(), Some(&map[..]));
+
+    // The first region is skipped without being read.
+    let mut regions = a.sparse_regions().unwrap();
+    let region = regions.next_region().unwrap().unwrap();
+    assert_eq!((region.offset(), region.len()), (0x1000, 0x1000));
+    let mut region = regions.next_region().unwrap().unwrap();
+    assert_eq!((region.offset(), region.len()), (0x3000, 0x1000));
+    let mut data = Vec::new();
+    region.read_to_end(&mut data).unwrap();
+    assert_eq!(&data[..6], b"world\n");
+    assert!(data[6..].iter().all(|x| *x == 0));
+    assert!(regions.next_region().unwrap().unwrap().is_empty());
+    assert!(regions.next_region().unwrap().is_none());
+    assert!(a.sparse_regions().is_err());
+
+    let mut ar = Archive::new(tar!("reading_files.tar"));
+    let mut a = ar.entries().unwrap().next().unwrap().unwrap();
+    assert_eq!(a.sparse_map(), None);
+    let mut regions = a.sparse_regions().unwrap();
+    let mut region = regions.next_region().unwrap().unwrap();
+    assert_eq!((region.offset(), region.len()), (0, 22));
+    let mut s = String::new();
+    region.read_to_string(&mut s).unwrap();
+    assert_eq!(s, "a\n".repeat(11));
+    assert!(regions.next_region().unwrap().is_none());
+}
+
+#[test]
+fn append_sparse() {
+    let mut image = vec![0; 20000];
+    image[0x1000..0x1006].copy_from_slice(b"hello\n");
+    image[0x3000..0x3006].copy_from_slice(b"world\n");
+    let regions = [(0x1000, 512), (0x3000, 6)];
+
+    for format in [tar::ArchiveFormat::Gnu, tar::ArchiveFormat::Pax] {
+        let mut ar = Builder::new(Vec::new());
+        ar.format(format);
+        let mut header = Header::new_gnu();
+        header.set_mode(0o644);
+        ar.append_sparse(&mut header, "sparse.txt", &regions, Cursor::new(&image))
+            .unwrap();
+        let data = ar.into_inner().unwrap();
+
+        let mut ar = Archive::new(&data[..]);
+        let mut entries = ar.entries().unwrap();
+        let mut a = entries.next().unwrap().unwrap();
+        assert_eq!(a.path().unwrap(), Path::new("sparse.txt"));
+        assert_eq!(a.size(), 20000);
+        let sparse = format == tar::ArchiveFormat::Gnu;
+        assert_eq!(a.header().entry_type().is_gnu_sparse(), sparse);
+        // The hole at the end is recorded, so that other tools extract the
+        // whole file.
+        let map = [(0x1000, 512), (0x3000, 6), (20000, 0)];
+        assert_eq!(a.sparse_map(), Some(&map[..]));
+        let mut contents = Vec::new();
+        a.read_to_end(&mut contents).unwrap();
+        assert!(contents == image);
+        assert!(entries.next().is_none());
+    }
+
+    // GNU sparse files need a GNU header, and nothing is written otherwise.
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_ustar();
+    let err = ar
+        .append_sparse(&mut header, "sparse.txt", &regions, Cursor::new(&image))
+        .unwrap_err();
+    assert!(err.to_string().contains("GNU header"));
+    let mut header = Header::new_gnu();
+    for regions in [[(0x1000, 6), (0x3000, 6)], [(0x1000, 512), (0x1100, 6)]] {
+        assert!(ar
+            .append_sparse(&mut header, "sparse.txt", &regions, Cursor::new(&image))
+            .is_err());
+    }
+    assert!(ar.get_ref().is_empty());
+}
+
+#[test]
 fn writing_sparse() {
+    for format in [tar::ArchiveFormat::Gnu, tar::ArchiveFormat::Pax] {
+        writing_sparse_in(format);
+    }
+}
+
+#[allow(clippy::option_map_unit_fn)]
+fn writing_sparse_in(format: tar::ArchiveFormat) {
     let mut ar = Builder::new(Vec::new());
+    ar.format(format);
     let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
 
     let mut files = Vec::new();
@@ -1484,11 +2529,19 @@ fn writing_sparse() {
 
     let data = ar.into_inner().unwrap();
 
-    // Without sparse support, the size of the tarball exceed 1MiB.
+    // Without sparse support, the size of the tarball exceed 1MiB. Pax
+    // sparse files take three more blocks each, for the pax header and the
+    // map of the file.
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    let extra = if format == tar::ArchiveFormat::Pax {
+        6 * 3 * 512
+    } else {
+        0
+    };
     #[cfg(target_os = "linux")]
-    assert!(data.len() <= 37 * 1024); // ext4 (defaults to 4k block size)
+    assert!(data.len() <= 37 * 1024 + extra); // ext4 (defaults to 4k block size)
     #[cfg(target_os = "freebsd")]
-    assert!(data.len() <= 273 * 1024); // UFS (defaults to 32k block size, last block isn't a hole)
+    assert!(data.len() <= 273 * 1024 + extra); // UFS (defaults to 32k block size, last block isn't a hole)
 
     let mut ar = Archive::new(&data[..]);
     let mut entries = ar.entries().unwrap();
@@ -1501,6 +2554,7 @@ fn writing_sparse() {
         let expected = fs::read_to_string(&path).unwrap();
 
         assert!(s == expected, "path: {path:?}");
+        assert_eq!(f.path().unwrap(), Path::new(path.file_name().unwrap()));
     }
 
     assert!(entries.next().is_none());
@@ -1676,6 +2730,121 @@ fn tar_directory_containing_symlink_to_directory() {
     ar.finish().unwrap();
 }
 
+#[test]
+fn unpack_with_filter() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_mode(0o555);
+    header.set_size(0);
+    ar.append_data(&mut header, "pkg-1.0/sub", io::empty())
+        .unwrap();
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(1);
+    ar.append_data(&mut header, "pkg-1.0/sub/a", &b"a"[..])
+        .unwrap();
+    ar.append_data(&mut header, "pkg-1.0/b", &b"b"[..]).unwrap();
+    ar.append_data(&mut header, "pkg-1.0/c", &b"c"[..]).unwrap();
+    let data = ar.into_inner().unwrap();
+
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+    let dst = td.path().join("dst");
+    let mut ar = Archive::new(&data[..]);
+    ar.unpack_with(&dst, |entry| {
+        let path = entry.path()?.into_owned();
+        Ok(match path.to_str().unwrap() {
+            "pkg-1.0/b" => None,
+            // Still can't escape the destination.
+            "pkg-1.0/c" => Some(PathBuf::from("../c")),
+            _ => Some(path.strip_prefix("pkg-1.0").unwrap().to_path_buf()),
+        })
+    })
+    .unwrap();
+
+    // The read-only directory is created after its contents.
+    assert_eq!(fs::read(dst.join("sub/a")).unwrap(), b"a");
+    assert!(dst.join("sub").is_dir());
+    assert!(!dst.join("b").exists());
+    assert!(!td.path().join("c").exists());
+    assert!(!dst.join("pkg-1.0").exists());
+}
+
+#[test]
+fn unpack_with_hard_links() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(1);
+    ar.append_data(&mut header, "./pkg-1.0/a", &b"a"[..])
+        .unwrap();
+    ar.append_data(&mut header, "top", &b"t"[..]).unwrap();
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Link);
+    header.set_mode(0o644);
+    header.set_size(0);
+    ar.append_link(&mut header, "pkg-1.0/b", "pkg-1.0/a")
+        .unwrap();
+    ar.append_link(&mut header, "pkg-1.0/c", "top").unwrap();
+    let data = ar.into_inner().unwrap();
+
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+    let mut ar = Archive::new(&data[..]);
+    // Stripping doesn't apply to the paths the filter returns, nor to the
+    // targets of hard links.
+    ar.set_strip_components(1);
+    ar.unpack_with(td.path(), |entry| {
+        let path = entry.path()?.into_owned();
+        Ok(Some(match path.strip_prefix("pkg-1.0") {
+            Ok(rest) => Path::new("renamed").join(rest),
+            Err(_) => path,
+        }))
+    })
+    .unwrap();
+
+    // Links follow their targets to where the filter moved them.
+    assert_eq!(fs::read(td.path().join("renamed/b")).unwrap(), b"a");
+    assert_eq!(fs::read(td.path().join("renamed/c")).unwrap(), b"t");
+    assert!(!td.path().join("pkg-1.0").exists());
+}
+
+#[test]
+fn unpack_strip_components() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_mode(0o755);
+    header.set_size(0);
+    ar.append_data(&mut header, "pkg-1.0/", io::empty())
+        .unwrap();
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(1);
+    ar.append_data(&mut header, "./pkg-1.0/sub/a", &b"a"[..])
+        .unwrap();
+    ar.append_data(&mut header, "top", &b"t"[..]).unwrap();
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Link);
+    header.set_mode(0o644);
+    header.set_size(0);
+    ar.append_link(&mut header, "pkg-1.0/b", "pkg-1.0/sub/a")
+        .unwrap();
+    ar.append_link(&mut header, "pkg-1.0/c", "top").unwrap();
+    let data = ar.into_inner().unwrap();
+
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+    let mut ar = Archive::new(&data[..]);
+    ar.set_strip_components(1);
+    ar.unpack(td.path()).unwrap();
+
+    assert_eq!(fs::read(td.path().join("sub/a")).unwrap(), b"a");
+    assert_eq!(fs::read(td.path().join("b")).unwrap(), b"a");
+    // Entries and links to entries left with no path are skipped.
+    assert!(!td.path().join("top").exists());
+    assert!(!td.path().join("c").exists());
+    assert!(!td.path().join("pkg-1.0").exists());
+}
+
 #[test]
 fn long_path() {
     let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
@@ -1770,29 +2939,96 @@ fn tar_directory_containing_special_files() {
 }
 
 #[test]
-fn header_size_overflow() {
-    // maximal file size doesn't overflow anything
+#[cfg(unix)]
+fn append_path_with_name_special_file() {
+    use std::ffi::CString;
+
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+    let fifo = td.path().join("fifo");
+    let fifo_path = CString::new(fifo.to_str().unwrap()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(fifo_path.as_ptr(), 0o644) }, 0);
+
     let mut ar = Builder::new(Vec::new());
-    let mut header = Header::new_gnu();
-    header.set_size(u64::MAX);
-    header.set_cksum();
-    ar.append(&header, "x".as_bytes()).unwrap();
-    let result = ar.into_inner().unwrap();
-    let mut ar = Archive::new(&result[..]);
-    let mut e = ar.entries().unwrap();
-    let err = e.next().unwrap().err().unwrap();
-    assert!(
-        err.to_string().contains("size overflow"),
-        "bad error: {}",
-        err
-    );
+    ar.append_path_with_name(&fifo, "renamed").unwrap();
+    let data = ar.into_inner().unwrap();
+
+    let mut ar = Archive::new(&data[..]);
+    let entry = ar.entries().unwrap().next().unwrap().unwrap();
+    assert_eq!(entry.header().entry_type(), EntryType::Fifo);
+    assert_eq!(entry.path().unwrap(), Path::new("renamed"));
+}
+
+#[test]
+#[cfg(unix)]
+fn unpack_special_files() {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+    use tar::SpecialFilePolicy;
 
-    // back-to-back entries that would overflow also don't panic
     let mut ar = Builder::new(Vec::new());
     let mut header = Header::new_gnu();
-    header.set_size(1_000);
-    header.set_cksum();
-    ar.append(&header, &[0u8; 1_000][..]).unwrap();
+    header.set_entry_type(EntryType::Fifo);
+    header.set_mode(0o640);
+    header.set_size(0);
+    ar.append_data(&mut header, "fifo", io::empty()).unwrap();
+    // The same device as /dev/null
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Char);
+    header.set_mode(0o666);
+    header.set_size(0);
+    header.set_device_major(1).unwrap();
+    header.set_device_minor(3).unwrap();
+    ar.append_data(&mut header, "null", io::empty()).unwrap();
+    let data = ar.into_inner().unwrap();
+
+    // Devices are skipped where they can't be created.
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+    Archive::new(&data[..]).unpack(td.path()).unwrap();
+    let meta = fs::metadata(td.path().join("fifo")).unwrap();
+    assert!(meta.file_type().is_fifo());
+    assert_eq!(meta.mode() & 0o777, 0o640);
+    let null = fs::metadata(td.path().join("null"));
+    if let Ok(null) = &null {
+        assert!(null.file_type().is_char_device());
+        assert_eq!(null.rdev(), fs::metadata("/dev/null").unwrap().rdev());
+    }
+
+    // Otherwise they fail, unless they were created above.
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+    let mut ar = Archive::new(&data[..]);
+    ar.set_special_file_policy(SpecialFilePolicy::Create);
+    assert_eq!(ar.unpack(td.path()).is_ok(), null.is_ok());
+
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+    let mut ar = Archive::new(&data[..]);
+    ar.set_special_file_policy(SpecialFilePolicy::Skip);
+    ar.unpack(td.path()).unwrap();
+    assert!(fs::read_dir(td.path()).unwrap().next().is_none());
+}
+
+#[test]
+fn header_size_overflow() {
+    // maximal file size doesn't overflow anything
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(u64::MAX);
+    header.set_cksum();
+    ar.append(&header, "x".as_bytes()).unwrap();
+    let result = ar.into_inner().unwrap();
+    let mut ar = Archive::new(&result[..]);
+    let mut e = ar.entries().unwrap();
+    let err = e.next().unwrap().err().unwrap();
+    assert!(
+        err.to_string().contains("size overflow"),
+        "bad error: {}",
+        err
+    );
+
+    // back-to-back entries that would overflow also don't panic
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(1_000);
+    header.set_cksum();
+    ar.append(&header, &[0u8; 1_000][..]).unwrap();
     let mut header = Header::new_gnu();
     header.set_size(u64::MAX - 513);
     header.set_cksum();
@@ -1884,6 +3120,146 @@ fn ownership_preserving() {
     }
 }
 
+#[test]
+#[cfg(unix)]
+fn ownership_by_name() {
+    use std::os::unix::prelude::*;
+    use tar::{IdLookup, OwnershipPolicy};
+
+    struct FakeLookup;
+
+    impl IdLookup for FakeLookup {
+        fn uid_for_user(&self, name: &str) -> io::Result<Option<u64>> {
+            Ok(if name == "alice" {
+                Some(580800100)
+            } else {
+                None
+            })
+        }
+
+        fn gid_for_group(&self, name: &str) -> io::Result<Option<u64>> {
+            Ok(if name == "staff" {
+                Some(580800200)
+            } else {
+                None
+            })
+        }
+    }
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    header.set_uid(580800001);
+    header.set_gid(580800002);
+    // names known to the lookup
+    header.set_username("alice").unwrap();
+    header.set_groupname("staff").unwrap();
+    ar.append_data(&mut header, "known", io::empty()).unwrap();
+    // names unknown to the lookup
+    header.set_username("mallory").unwrap();
+    header.set_groupname("nobody").unwrap();
+    ar.append_data(&mut header, "unknown", io::empty()).unwrap();
+    // pax records override the header
+    ar.append_pax_extensions([("uname", b"alice".as_slice())])
+        .unwrap();
+    ar.append_data(&mut header, "pax", io::empty()).unwrap();
+    let data = ar.into_inner().unwrap();
+
+    if unsafe { libc::getuid() } != 0 {
+        return;
+    }
+
+    let owners = |policy| {
+        let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+        let mut ar = Archive::new(&data[..]);
+        ar.set_preserve_ownerships(true);
+        ar.set_ownership_policy(policy);
+        ar.set_id_lookup(FakeLookup);
+        ar.unpack(td.path()).unwrap();
+        ["known", "unknown", "pax"].map(|name| {
+            let meta = fs::metadata(td.path().join(name)).unwrap();
+            (meta.uid(), meta.gid())
+        })
+    };
+    assert_eq!(
+        owners(OwnershipPolicy::Numeric),
+        [(580800001, 580800002); 3]
+    );
+    assert_eq!(
+        owners(OwnershipPolicy::PreferNames),
+        [
+            (580800100, 580800200),
+            (580800001, 580800002),
+            (580800100, 580800002),
+        ]
+    );
+    assert_eq!(
+        owners(OwnershipPolicy::NamesOnly),
+        [(580800100, 580800200), (0, 0), (580800100, 0)]
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn ownership_id_maps() {
+    use std::os::unix::prelude::*;
+    use tar::IdMap;
+
+    let map: IdMap = "0 580800000 65536\n".parse().unwrap();
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    header.set_uid(1000);
+    header.set_gid(100);
+    ar.append_data(&mut header, "a", io::empty()).unwrap();
+    let data = ar.into_inner().unwrap();
+
+    let td = TempBuilder::new().prefix("tar-rs").tempdir().unwrap();
+    let mut ar = Archive::new(&data[..]);
+    ar.set_preserve_ownerships(true);
+    ar.set_uid_map(map.clone());
+    ar.set_gid_map(map.clone());
+    if unsafe { libc::getuid() } == 0 {
+        ar.unpack(td.path()).unwrap();
+        let meta = fs::metadata(td.path().join("a")).unwrap();
+        assert_eq!(meta.uid(), 580801000);
+        assert_eq!(meta.gid(), 580800100);
+    }
+
+    // ids outside of the map are rejected
+    let mut ar = Archive::new(&data[..]);
+    ar.set_preserve_ownerships(true);
+    let mut map = IdMap::new();
+    map.add_range(0, 580800000, 1000);
+    ar.set_uid_map(map);
+    let err = ar.unpack(td.path().join("b")).unwrap_err();
+    assert!(
+        format!("{:?}", err).contains("not mapped"),
+        "bad error: {:?}",
+        err
+    );
+
+    // and the builder maps host ids back into the archive
+    let path = td.path().join("c");
+    fs::write(&path, b"c").unwrap();
+    let meta = fs::metadata(&path).unwrap();
+    let mut map = IdMap::new();
+    map.add_range(1000, meta.uid().into(), 1);
+    let mut ar = Builder::new(Vec::new());
+    ar.uid_map(map);
+    ar.append_path_with_name(&path, "c").unwrap();
+    let data = ar.into_inner().unwrap();
+    let mut ar = Archive::new(&data[..]);
+    let e = ar.entries().unwrap().next().unwrap().unwrap();
+    assert_eq!(e.header().uid().unwrap(), 1000);
+    assert_eq!(e.header().gid().unwrap(), u64::from(meta.gid()));
+
+    let mut ar = Builder::new(Vec::new());
+    ar.gid_map(IdMap::new());
+    assert!(ar.append_path_with_name(&path, "c").is_err());
+}
+
 #[test]
 #[cfg(unix)]
 fn pax_and_gnu_uid_gid() {
@@ -1920,7 +3296,7 @@ fn append_data_error_does_not_corrupt_subsequent_entries() {
 
     // First write: a long path (>100 bytes to trigger GNU long-name extension)
     // containing ".." not as the last component, which will fail validation
-    // in set_truncated_path_for_gnu_header.
+    // in set_truncated_path.
     let dotdot_path = "a/../b/".to_string() + &"c".repeat(100);
     let mut header = Header::new_gnu();
     header.set_size(5);
@@ -1967,7 +3343,7 @@ fn build_pax_smuggle_archive() -> Vec<u8> {
     let pax_rec = format!("13 size={INFLATED}\n");
     let mut pax_hdr = Header::new_ustar();
     pax_hdr.set_path("./PaxHeaders/regular").unwrap();
-    pax_hdr.set_size(pax_rec.as_bytes().len() as u64);
+    pax_hdr.set_size(pax_rec.len() as u64);
     pax_hdr.set_entry_type(EntryType::XHeader);
     pax_hdr.set_cksum();
     ar.extend_from_slice(pax_hdr.as_bytes());
@@ -2100,3 +3476,613 @@ async fn pax_size_smuggle_matches_astral_tokio_tar() {
          got: {async_entries:?}"
     );
 }
+
+/// The path, link name, size, header position and contents of an entry.
+type EntrySummary = (Vec<u8>, Option<Vec<u8>>, u64, u64, Vec<u8>);
+
+/// Reads every entry of `data` with the blocking reader, recording the
+/// metadata and first megabyte of contents of each or the error which stopped
+/// iteration.
+fn sync_entries(data: &[u8]) -> Vec<Result<EntrySummary, String>> {
+    let mut ar = Archive::new(data);
+    let mut result = Vec::new();
+    let entries = match ar.entries() {
+        Ok(entries) => entries,
+        Err(e) => return vec![Err(e.to_string())],
+    };
+    for entry in entries {
+        let mut entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                result.push(Err(e.to_string()));
+                break;
+            }
+        };
+        let mut contents = Vec::new();
+        if let Err(e) = (&mut entry).take(1 << 20).read_to_end(&mut contents) {
+            result.push(Err(e.to_string()));
+            break;
+        }
+        result.push(Ok((
+            entry.path_bytes().into_owned(),
+            entry.link_name_bytes().map(|l| l.into_owned()),
+            entry.size(),
+            entry.raw_header_position(),
+            contents,
+        )));
+    }
+    result
+}
+
+#[test]
+fn decoder_matches_archive() {
+    for file in fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/archives")).unwrap() {
+        let path = file.unwrap().path();
+        if path.extension().map_or(true, |ext| ext != "tar") {
+            continue;
+        }
+        let data = fs::read(&path).unwrap();
+
+        // Feed the decoder chunks of random sizes, which split headers and
+        // contents at arbitrary points.
+        let mut rng = SmallRng::seed_from_u64(data.len() as u64);
+        let mut decoder = Decoder::new();
+        let mut result: Vec<Result<EntrySummary, String>> = Vec::new();
+        let mut input = &data[..0];
+        let mut offset = 0;
+        loop {
+            let decoded = if !input.is_empty() {
+                decoder.decode(input).map(|(n, decoded)| {
+                    input = &input[n..];
+                    decoded
+                })
+            } else if offset < data.len() {
+                let len = rng.gen_range(1..=2048).min(data.len() - offset);
+                input = &data[offset..offset + len];
+                offset += len;
+                continue;
+            } else {
+                decoder.finish().map(Some)
+            };
+            let contents = match result.last_mut() {
+                Some(Ok(entry)) => Some(&mut entry.4),
+                _ => None,
+            };
+            match decoded {
+                Ok(Some(Decoded::Entry(entry))) => result.push(Ok((
+                    entry.path_bytes().into_owned(),
+                    entry.link_name_bytes().map(|l| l.into_owned()),
+                    entry.size(),
+                    entry.raw_header_position(),
+                    Vec::new(),
+                ))),
+                Ok(Some(Decoded::Data(data))) => {
+                    let contents = contents.unwrap();
+                    let n = data.len().min((1 << 20) - contents.len());
+                    contents.extend_from_slice(&data[..n]);
+                }
+                Ok(Some(Decoded::Hole(len))) => {
+                    let contents = contents.unwrap();
+                    let n = (len as usize).min((1 << 20) - contents.len());
+                    contents.resize(contents.len() + n, 0);
+                }
+                Ok(Some(Decoded::End)) => break,
+                Ok(None) => {}
+                Err(e) => {
+                    result.push(Err(e.to_string()));
+                    break;
+                }
+            }
+        }
+        assert!(
+            result == sync_entries(&data),
+            "{} decoded differently",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn append_entry_copies_archives() {
+    for file in fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/archives")).unwrap() {
+        let path = file.unwrap().path();
+        if path.extension().map_or(true, |ext| ext != "tar") {
+            continue;
+        }
+        let data = fs::read(&path).unwrap();
+        let expected = sync_entries(&data);
+        if expected.iter().any(|e| e.is_err()) {
+            continue;
+        }
+
+        let mut ar = Archive::new(&data[..]);
+        let mut copy = Builder::new(Vec::new());
+        for entry in ar.entries().unwrap() {
+            copy.append_entry(&mut entry.unwrap()).unwrap();
+        }
+        let copy = copy.into_inner().unwrap();
+
+        // Everything but the positions of headers is carried over.
+        let strip = |entries: Vec<Result<EntrySummary, String>>| {
+            entries
+                .into_iter()
+                .map(|e| {
+                    let (path, link, size, _, contents) = e.unwrap();
+                    (path, link, size, contents)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert!(
+            strip(sync_entries(&copy)) == strip(expected),
+            "{} copied differently",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn append_entry_copies_global_headers_verbatim() {
+    let mut ar = Builder::new(Vec::new());
+    ar.append_pax_global_extensions([
+        ("comment", &b"abc123"[..]),
+        ("uid", b"1234"),
+        ("mtime", b"1000000000"),
+    ])
+    .unwrap();
+    let mut header = Header::new_gnu();
+    header.set_size(5);
+    ar.append_data(&mut header, "a", &b"hello"[..]).unwrap();
+    ar.append_pax_extensions([("uid", &b"99"[..])]).unwrap();
+    let mut header = Header::new_ustar();
+    header.set_size(5);
+    ar.append_data(&mut header, "b", &b"world"[..]).unwrap();
+    let data = ar.into_inner().unwrap();
+
+    // Global records apply to the entries, but aren't copied into them.
+    let mut src = Archive::new(&data[..]);
+    let mut ar = Builder::new(Vec::new());
+    for entry in src.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        if entry.path().unwrap() == Path::new("a") {
+            assert_eq!(entry.header().uid().unwrap(), 1234);
+        }
+        ar.append_entry(&mut entry).unwrap();
+    }
+    assert!(ar.into_inner().unwrap() == data);
+}
+
+#[test]
+fn append_entry_with_modifications() {
+    let long_path = format!("{}/b", "a".repeat(300));
+    let mut ar = Builder::new(Vec::new());
+    ar.append_pax_extensions([("mtime", &b"1234.5"[..]), ("comment", b"kept")])
+        .unwrap();
+    let mut header = Header::new_ustar();
+    header.set_size(5);
+    header.set_mtime(1234);
+    ar.append_data(&mut header, "a", &b"hello"[..]).unwrap();
+    let mut header = Header::new_gnu();
+    header.set_size(5);
+    ar.append_data(&mut header, "b", &b"world"[..]).unwrap();
+    let data = ar.into_inner().unwrap();
+
+    let mut src = Archive::new(&data[..]);
+    let mut ar = Builder::new(Vec::new());
+    for entry in src.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        if entry.path().unwrap() == Path::new("b") {
+            // Contents which were read can't be copied.
+            entry.read_exact(&mut [0; 1]).unwrap();
+            assert!(ar.append_entry(&mut entry).is_err());
+            continue;
+        }
+        ar.append_entry_with(&mut entry, |path, header| {
+            *path = PathBuf::from(&long_path);
+            header.set_mtime(0);
+            Ok(())
+        })
+        .unwrap();
+    }
+    let data = ar.into_inner().unwrap();
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = ar.entries().unwrap();
+    let mut a = entries.next().unwrap().unwrap();
+    assert_eq!(a.path().unwrap(), Path::new(&long_path));
+    assert_eq!(a.header().mtime().unwrap(), 0);
+    assert_eq!(a.mtime().unwrap(), std::time::UNIX_EPOCH);
+    let pax = a.pax_extensions().unwrap().unwrap();
+    let keys = pax.map(|e| e.unwrap().key().unwrap().to_string());
+    assert_eq!(keys.collect::<Vec<_>>(), ["comment", "path"]);
+    let mut s = String::new();
+    a.read_to_string(&mut s).unwrap();
+    assert_eq!(s, "hello");
+    assert!(entries.next().is_none());
+}
+
+#[test]
+fn index_open_entries() {
+    let long_path = format!("{}/b", "a".repeat(150));
+    let mut ar = Builder::new(Vec::new());
+    for (path, len) in [("a", 3000), (&long_path[..], 600), ("a", 10)] {
+        let mut header = Header::new_gnu();
+        header.set_size(len as u64);
+        header.set_cksum();
+        let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        ar.append_data(&mut header, path, &data[..]).unwrap();
+    }
+    let mut ar = Archive::new(Cursor::new(ar.into_inner().unwrap()));
+    let index = Index::build(&mut ar).unwrap();
+    let mut rdr = ar.into_inner();
+
+    assert_eq!(index.entries().len(), 3);
+    assert_eq!(index.entries()[0].size(), 3000);
+    // The last of entries with the same path wins.
+    assert_eq!(index.get("a").unwrap(), &index.entries()[2]);
+    assert!(index.get("b").is_none());
+
+    let mut long = index.get(&long_path).unwrap().open(&mut rdr).unwrap();
+    let mut contents = Vec::new();
+    long.read_to_end(&mut contents).unwrap();
+    assert_eq!(contents.len(), 600);
+    assert!(contents
+        .iter()
+        .enumerate()
+        .all(|(i, b)| *b == (i % 251) as u8));
+
+    let mut first = index.entries()[0].open(&mut rdr).unwrap();
+    assert_eq!(first.header().path().unwrap(), Path::new("a"));
+    first.seek(io::SeekFrom::Start(2990)).unwrap();
+    let mut contents = Vec::new();
+    first.read_to_end(&mut contents).unwrap();
+    assert_eq!(
+        contents,
+        (2990..3000).map(|i| (i % 251) as u8).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn index_open_sparse() {
+    for archive in [
+        tar!("sparse.tar"),
+        tar!("pax-sparse-0.1.tar"),
+        tar!("pax-sparse-1.0.tar"),
+    ] {
+        let mut ar = Archive::new(Cursor::new(archive));
+        let index = Index::build(&mut ar).unwrap();
+        let mut rdr = ar.into_inner();
+        assert!(index.entries()[0].sparse_map().is_some());
+
+        // Open the entries in reverse and compare them with a sequential read
+        let mut ar = Archive::new(archive);
+        let expected = ar
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let mut contents = Vec::new();
+                e.unwrap().read_to_end(&mut contents).unwrap();
+                contents
+            })
+            .collect::<Vec<_>>();
+        for (entry, expected) in index.entries().iter().zip(&expected).rev() {
+            let mut contents = Vec::new();
+            let mut rdr = entry.open(&mut rdr).unwrap();
+            rdr.read_to_end(&mut contents).unwrap();
+            assert!(&contents == expected, "{}", entry.path().display());
+        }
+    }
+}
+
+#[test]
+fn index_write_and_read() {
+    let mut ar = Archive::new(Cursor::new(tar!("sparse.tar")));
+    let index = Index::build(&mut ar).unwrap();
+    let mut rdr = ar.into_inner();
+
+    let mut saved = Vec::new();
+    index.write_to(&mut saved).unwrap();
+    assert_eq!(&saved[..8], b"tar-idx\0");
+    let loaded = Index::read_from(&saved[..]).unwrap();
+    assert!(loaded.entries() == index.entries());
+
+    let mut s = String::new();
+    loaded
+        .get("sparse_begin.txt")
+        .unwrap()
+        .open(&mut rdr)
+        .unwrap()
+        .read_to_string(&mut s)
+        .unwrap();
+    assert_eq!(&s[..5], "test\n");
+
+    // Unknown versions and truncated indexes are rejected.
+    let mut newer = saved.clone();
+    newer[8] = 2;
+    let err = Index::read_from(&newer[..]).err().unwrap();
+    assert!(err.to_string().contains("unsupported tar index version 2"));
+    assert!(Index::read_from(&saved[..saved.len() - 1]).is_err());
+    assert!(Index::read_from(&b"not an index"[..]).is_err());
+}
+
+#[test]
+fn index_read_rejects_invalid_entries() {
+    fn index(header_pos: u64, file_pos: u64, size: u64, sparse: Option<&[(u64, u64)]>) -> Vec<u8> {
+        let mut data = b"tar-idx\0".to_vec();
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&1u64.to_le_bytes());
+        for n in [header_pos, file_pos, size] {
+            data.extend_from_slice(&n.to_le_bytes());
+        }
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.push(b'a');
+        match sparse {
+            Some(blocks) => {
+                data.extend_from_slice(&(blocks.len() as u64).to_le_bytes());
+                for &(off, len) in blocks {
+                    data.extend_from_slice(&off.to_le_bytes());
+                    data.extend_from_slice(&len.to_le_bytes());
+                }
+            }
+            None => data.extend_from_slice(&u64::MAX.to_le_bytes()),
+        }
+        data
+    }
+
+    assert!(Index::read_from(&index(0, 512, 10, None)[..]).is_ok());
+    assert!(Index::read_from(&index(0, 512, 10, Some(&[(0, 2), (5, 5)]))[..]).is_ok());
+
+    let invalid = [
+        index(u64::MAX, 512, 10, None),
+        index(0, u64::MAX, 10, None),
+        index(0, 512, 10, Some(&[(5, 2), (0, 2)])),
+        index(0, 512, 10, Some(&[(0, 4), (2, 4)])),
+        index(0, 512, 10, Some(&[(8, 4)])),
+        index(0, 512, u64::MAX, Some(&[(u64::MAX, 2)])),
+        index(0, 512, u64::MAX, Some(&[(0, u64::MAX), (u64::MAX, 0)])),
+        index(0, u64::MAX - 4, 10, Some(&[(0, 2), (4, 4)])),
+    ];
+    for data in &invalid {
+        let err = Index::read_from(&data[..]).err().unwrap();
+        assert!(
+            err.to_string().contains("invalid tar index entry"),
+            "{}",
+            err
+        );
+    }
+}
+
+/// A "digest" which is all of the data hashed, to check what's covered.
+#[derive(Default)]
+struct Identity(Vec<u8>);
+
+impl tar::Digest for Identity {
+    fn new_digest(&self) -> Box<dyn tar::Digest> {
+        Box::new(Identity::default())
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.0.extend_from_slice(data);
+    }
+
+    fn finish(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+}
+
+#[test]
+fn builder_digests() {
+    let mut image = vec![0; 20000];
+    image[0x1000..0x1006].copy_from_slice(b"hello\n");
+
+    let mut ar = Builder::new(Cursor::new(Vec::new()));
+    ar.digest(Identity::default());
+    let mut header = Header::new_gnu();
+    header.set_size(6);
+    ar.append_data(&mut header, "a.txt", &b"hello\n"[..])
+        .unwrap();
+    let digests = ar.take_entry_digests();
+    assert_eq!(digests.len(), 1);
+    assert_eq!(digests[0].path_bytes(), b"a.txt");
+    assert_eq!(digests[0].digest(), b"hello\n");
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    ar.append_link(&mut header, "link", "a.txt").unwrap();
+    let mut header = Header::new_gnu();
+    ar.append_sparse(
+        &mut header,
+        "sparse.txt",
+        &[(0x1000, 6)],
+        Cursor::new(&image),
+    )
+    .unwrap();
+    assert!(ar.append_writer(&mut Header::new_gnu(), "b.txt").is_err());
+    ar.finish().unwrap();
+
+    let digests = ar.take_entry_digests();
+    let digests = digests
+        .iter()
+        .map(|d| (d.path().unwrap().into_owned(), d.digest()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        digests,
+        [
+            (PathBuf::from("link"), &b""[..]),
+            (PathBuf::from("sparse.txt"), &image[..]),
+        ]
+    );
+    assert!(ar.take_entry_digests().is_empty());
+    let digest = ar.archive_digest().unwrap();
+    assert_eq!(digest, ar.into_inner().unwrap().into_inner());
+
+    // Headers the digests can't follow are rejected before being written.
+    let mut ar = Builder::new(Vec::new());
+    ar.digest(Identity::default());
+    let mut header = Header::new_gnu();
+    header.set_path("bad.txt").unwrap();
+    header.set_size(1);
+    assert!(ar.append(&header, &b"a"[..]).is_err());
+    assert!(ar.get_ref().is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn builder_digests_non_utf8_path() {
+    use std::ffi::OsStr;
+    use std::os::unix::prelude::*;
+
+    let mut ar = Builder::new(Vec::new());
+    ar.digest(Identity::default());
+    let mut header = Header::new_gnu();
+    header.set_size(1);
+    ar.append_data(&mut header, OsStr::from_bytes(b"\xff.txt"), &b"a"[..])
+        .unwrap();
+    let digests = ar.take_entry_digests();
+    assert_eq!(digests.len(), 1);
+    assert_eq!(digests[0].path_bytes(), b"\xff.txt");
+    assert_eq!(digests[0].digest(), b"a");
+}
+
+#[test]
+fn archive_digests() {
+    for data in [tar!("reading_files.tar"), tar!("pax-sparse-1.0.tar")] {
+        let mut expected = Vec::new();
+        for entry in Archive::new(data).entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents).unwrap();
+            expected.push(contents);
+        }
+
+        // Contents are hashed whether or not they're read.
+        let mut ar = Archive::new(Cursor::new(data));
+        ar.set_digest(Identity::default());
+        let mut entries = ar.entries_with_seek().unwrap();
+        for (i, expected) in expected.iter().enumerate() {
+            let mut entry = entries.next().unwrap().unwrap();
+            if i % 2 == 0 {
+                assert_eq!(entry.digest(), None);
+                io::copy(&mut entry, &mut io::sink()).unwrap();
+                assert_eq!(entry.digest().as_ref(), Some(expected));
+            }
+        }
+        assert!(entries.next().is_none());
+        assert_eq!(ar.archive_digest().unwrap(), data);
+    }
+}
+
+#[cfg(feature = "sha2")]
+#[test]
+fn sha256_digests() {
+    let mut ar = Builder::new(Vec::new());
+    ar.digest(tar::Sha256::new());
+    let mut header = Header::new_gnu();
+    header.set_size(6);
+    ar.append_data(&mut header, "a.txt", &b"hello\n"[..])
+        .unwrap();
+    let digests = ar.take_entry_digests();
+    let hex = digests[0]
+        .digest()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    assert_eq!(
+        hex,
+        "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"
+    );
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_archive_matches_archive() {
+    use tokio::io::AsyncReadExt;
+
+    for file in fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/archives")).unwrap() {
+        let path = file.unwrap().path();
+        if path.extension().map_or(true, |ext| ext != "tar") {
+            continue;
+        }
+        let data = fs::read(&path).unwrap();
+
+        let mut ar = tar::AsyncArchive::new(&data[..]);
+        let mut entries = ar.entries().unwrap();
+        let mut result = Vec::new();
+        while let Some(entry) = entries.next().await {
+            let mut entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    result.push(Err(e.to_string()));
+                    break;
+                }
+            };
+            let mut contents = Vec::new();
+            if let Err(e) = (&mut entry).take(1 << 20).read_to_end(&mut contents).await {
+                result.push(Err(e.to_string()));
+                break;
+            }
+            result.push(Ok((
+                entry.path_bytes().into_owned(),
+                entry.link_name_bytes().map(|l| l.into_owned()),
+                entry.size(),
+                entry.raw_header_position(),
+                contents,
+            )));
+        }
+        assert!(
+            result == sync_entries(&data),
+            "{} read differently",
+            path.display()
+        );
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_builder() {
+    let long_path = "a".repeat(200);
+    let long_target = "b".repeat(200);
+    let mut archives = Vec::new();
+    for format in [tar::ArchiveFormat::Gnu, tar::ArchiveFormat::Pax] {
+        let mut ar = tar::AsyncBuilder::new(Vec::new());
+        ar.format(format);
+        let mut header = Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(5);
+        ar.append_data(&mut header, &long_path, &b"hello"[..])
+            .await
+            .unwrap();
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_size(0);
+        ar.append_link(&mut header, "link", &long_target)
+            .await
+            .unwrap();
+        let data = ar.into_inner().await.unwrap();
+
+        // The blocking builder produces the same archive.
+        let mut ar = Builder::new(Vec::new());
+        ar.format(format);
+        let mut header = Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(5);
+        ar.append_data(&mut header, &long_path, &b"hello"[..])
+            .unwrap();
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_size(0);
+        ar.append_link(&mut header, "link", &long_target).unwrap();
+        assert!(data == ar.into_inner().unwrap());
+        archives.push(data);
+    }
+
+    let mut ar = Archive::new(&archives[1][..]);
+    let mut entries = ar.entries().unwrap();
+    let mut a = entries.next().unwrap().unwrap();
+    assert_eq!(a.path().unwrap(), Path::new(&long_path));
+    let mut s = String::new();
+    a.read_to_string(&mut s).unwrap();
+    assert_eq!(s, "hello");
+    let b = entries.next().unwrap().unwrap();
+    assert_eq!(b.link_name().unwrap().unwrap(), Path::new(&long_target));
+    assert!(entries.next().is_none());
+}
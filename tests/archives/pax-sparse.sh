@@ -0,0 +1,9 @@
+#!/bin/sh
+set -eu
+truncate -s 20000 sparse.txt
+printf 'hello\n' | dd of=sparse.txt bs=1 seek=4096 conv=notrunc
+printf 'world\n' | dd of=sparse.txt bs=1 seek=12288 conv=notrunc
+for v in 0.0 0.1 1.0; do
+  tar --format=pax --sparse --sparse-version="$v" -b 1 -cf pax-sparse-"$v".tar sparse.txt
+done
+rm sparse.txt